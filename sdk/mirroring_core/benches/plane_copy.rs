@@ -0,0 +1,39 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use mcore::plane_copy::copy_plane;
+use std::hint::black_box;
+
+const WIDTH: usize = 2560;
+const HEIGHT: usize = 1440;
+/// Padded rows like some `ImageReader` implementations produce, so every row is copied on its own
+const ROW_STRIDE: usize = WIDTH + 64;
+
+fn bench_copy(c: &mut Criterion, name: &str, (row_len, rows): (usize, usize), pixel_stride: usize) {
+    let src = vec![0x80u8; (rows - 1) * ROW_STRIDE + (row_len - 1) * pixel_stride + 1];
+    let dest_stride = row_len.next_multiple_of(4);
+    let mut dest = vec![0u8; dest_stride * rows];
+
+    let mut group = c.benchmark_group("copy_plane");
+    group.throughput(Throughput::Bytes((row_len * rows) as u64));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            copy_plane(
+                black_box(&mut dest),
+                dest_stride,
+                black_box(&src),
+                (row_len, rows),
+                ROW_STRIDE,
+                pixel_stride,
+            )
+        })
+    });
+    group.finish();
+}
+
+fn copy_planes(c: &mut Criterion) {
+    bench_copy(c, "luma_1440p", (WIDTH, HEIGHT), 1);
+    bench_copy(c, "chroma_1440p_planar", (WIDTH / 2, HEIGHT / 2), 1);
+    bench_copy(c, "chroma_1440p_interleaved", (WIDTH / 2, HEIGHT / 2), 2);
+}
+
+criterion_group!(benches, copy_planes);
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+#[cfg(not(target_os = "android"))]
+use crate::DeviceEvent;
+use crate::Event;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::mpsc::{self, error::SendError, error::TrySendError};
+use tracing::warn;
+
+/// Maximum number of telemetry events that can be waiting for the event loop before new ones are
+/// dropped.
+const TELEMETRY_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Never dropped and always handled before any pending telemetry
+    Control,
+    /// Frequent updates where a newer event supersedes older ones, dropped when the event loop
+    /// falls behind
+    Telemetry,
+}
+
+pub fn priority(event: &Event) -> Priority {
+    // Discovery events stay on the control queue, they have to be handled in the order they were
+    // sent or a receiver that was removed could come back from an older update
+    match event {
+        #[cfg(not(target_os = "android"))]
+        Event::FromDevice {
+            event:
+                DeviceEvent::TimeChanged(_)
+                | DeviceEvent::VolumeChanged(_)
+                | DeviceEvent::DurationChanged(_)
+                | DeviceEvent::SpeedChanged(_),
+            ..
+        } => Priority::Telemetry,
+        // The receiver's playback telemetry is not forwarded on Android (see `DeviceHandler`),
+        // the frequent events there are network callbacks and the periodic reachability probes
+        #[cfg(target_os = "android")]
+        Event::NetworkChanged { .. } | Event::DeviceProbed { .. } => Priority::Telemetry,
+        _ => Priority::Control,
+    }
+}
+
+/// Create a new event bus. Control events are unbounded, telemetry is bounded and dropped when
+/// the receiver falls behind so it can never delay or starve control events.
+pub fn channel() -> (EventSender, EventReceiver) {
+    let (control_tx, control_rx) = mpsc::unbounded_channel();
+    let (telemetry_tx, telemetry_rx) = mpsc::channel(TELEMETRY_CAPACITY);
+    let dropped = Arc::new(AtomicU64::new(0));
+    (
+        EventSender {
+            control: control_tx,
+            telemetry: telemetry_tx,
+            dropped: Arc::clone(&dropped),
+        },
+        EventReceiver {
+            control: control_rx,
+            telemetry: telemetry_rx,
+            dropped,
+        },
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    control: mpsc::UnboundedSender<Event>,
+    telemetry: mpsc::Sender<Event>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventSender {
+    /// Send an event without blocking. A full telemetry queue is not an error, the event is
+    /// dropped and counted instead.
+    pub fn send(&self, event: Event) -> Result<(), SendError<Event>> {
+        match priority(&event) {
+            Priority::Control => self.control.send(event),
+            Priority::Telemetry => match self.telemetry.try_send(event) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    if dropped.is_power_of_two() {
+                        warn!(dropped, "Event loop is falling behind, dropping telemetry");
+                    }
+                    Ok(())
+                }
+                Err(TrySendError::Closed(event)) => Err(SendError(event)),
+            },
+        }
+    }
+
+    /// Number of telemetry events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct EventReceiver {
+    control: mpsc::UnboundedReceiver<Event>,
+    telemetry: mpsc::Receiver<Event>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventReceiver {
+    /// Receive the next event, control events first. Returns `None` when all senders are gone.
+    ///
+    /// Cancel safe.
+    pub async fn recv(&mut self) -> Option<Event> {
+        tokio::select! {
+            biased;
+            Some(event) = self.control.recv() => Some(event),
+            Some(event) = self.telemetry.recv() => Some(event),
+            else => None,
+        }
+    }
+
+    /// Number of telemetry events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fcast_sender_sdk::device::DeviceInfo;
+
+    #[cfg(not(target_os = "android"))]
+    fn telemetry_event() -> Event {
+        Event::FromDevice {
+            id: 0,
+            event: DeviceEvent::TimeChanged(1.0),
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn is_telemetry_event(event: Option<Event>) -> bool {
+        matches!(
+            event,
+            Some(Event::FromDevice {
+                event: DeviceEvent::TimeChanged(_),
+                ..
+            })
+        )
+    }
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo::fcast("test".to_owned(), Vec::new(), 46899)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn control_is_received_before_telemetry() {
+        let (tx, mut rx) = channel();
+        tx.send(telemetry_event()).unwrap();
+        tx.send(Event::Quit).unwrap();
+
+        assert!(matches!(rx.recv().await, Some(Event::Quit)));
+        assert!(is_telemetry_event(rx.recv().await));
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn telemetry_is_dropped_when_full() {
+        let (tx, mut rx) = channel();
+        for _ in 0..TELEMETRY_CAPACITY + 5 {
+            tx.send(telemetry_event()).unwrap();
+        }
+        tx.send(Event::Quit).unwrap();
+
+        assert_eq!(rx.dropped(), 5);
+        assert!(matches!(rx.recv().await, Some(Event::Quit)));
+        for _ in 0..TELEMETRY_CAPACITY {
+            assert!(is_telemetry_event(rx.recv().await));
+        }
+    }
+
+    #[tokio::test]
+    async fn device_removed_then_changed_keeps_order() {
+        let (tx, mut rx) = channel();
+        tx.send(Event::DeviceRemoved("test".to_owned())).unwrap();
+        tx.send(Event::DeviceChanged(device_info())).unwrap();
+        tx.send(Event::DeviceRemoved("test".to_owned())).unwrap();
+
+        assert!(matches!(rx.recv().await, Some(Event::DeviceRemoved(_))));
+        assert!(matches!(rx.recv().await, Some(Event::DeviceChanged(_))));
+        assert!(matches!(rx.recv().await, Some(Event::DeviceRemoved(_))));
+    }
+
+    #[tokio::test]
+    async fn device_changed_is_never_dropped() {
+        let (tx, mut rx) = channel();
+        for _ in 0..TELEMETRY_CAPACITY + 5 {
+            tx.send(Event::DeviceChanged(device_info())).unwrap();
+        }
+
+        assert_eq!(rx.dropped(), 0);
+        for _ in 0..TELEMETRY_CAPACITY + 5 {
+            assert!(matches!(rx.recv().await, Some(Event::DeviceChanged(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_returns_none_when_senders_are_gone() {
+        let (tx, mut rx) = channel();
+        tx.send(Event::Quit).unwrap();
+        drop(tx);
+
+        assert!(matches!(rx.recv().await, Some(Event::Quit)));
+        assert!(rx.recv().await.is_none());
+    }
+}
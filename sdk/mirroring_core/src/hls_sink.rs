@@ -0,0 +1,363 @@
+//! Screen casting over HLS for receivers that can't play WHEP streams. The stream is a few seconds
+//! behind instead of a few hundred milliseconds, but any receiver that can load a URL can play it.
+
+use crate::{AudioSource, Event, SinkId, SourceConfig, VideoSource};
+use bytes::Bytes;
+use gst::prelude::*;
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{Method, Request, Response, StatusCode, header};
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{debug, error};
+
+const PLAYLIST_NAME: &str = "playlist.m3u8";
+const SEGMENT_PATTERN: &str = "segment%05d.ts";
+/// Segment length in seconds, this is most of the latency HLS adds
+const TARGET_DURATION: u32 = 1;
+const PLAYLIST_LENGTH: u32 = 3;
+/// Segments kept on disk, a few more than the playlist lists so slow receivers can finish
+/// downloading the ones that just dropped out of it
+const MAX_FILES: u32 = 6;
+const VIDEO_BITRATE_KBPS: u32 = 6000;
+/// Frames between keyframes, every segment has to start with one
+const KEYFRAME_INTERVAL: u32 = 30;
+/// How long a request for the playlist is held while the first segment is being written
+const PLAYLIST_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+const PLAYLIST_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn content_type_from_name(name: &str) -> Option<&'static str> {
+    match name.rsplit_once('.')?.1 {
+        "m3u8" => Some("application/vnd.apple.mpegurl"),
+        "ts" => Some("video/mp2t"),
+        _ => None,
+    }
+}
+
+fn body_full(data: Bytes) -> BoxBody<Bytes, hyper::Error> {
+    http_body_util::Full::new(data)
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+fn not_found() -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::http::Error> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(body_full(Bytes::new()))
+}
+
+async fn handle_request(
+    req: Request<hyper::body::Incoming>,
+    dir: &Path,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::http::Error> {
+    let name = req.uri().path().trim_start_matches('/');
+    let content_type = match (req.method(), content_type_from_name(name)) {
+        (&Method::GET | &Method::HEAD, Some(content_type)) if !name.contains(['/', '\\']) => {
+            content_type
+        }
+        _ => return not_found(),
+    };
+
+    let path = dir.join(name);
+    if name == PLAYLIST_NAME {
+        // hlssink2 only writes the playlist once the first segment is complete
+        let deadline = tokio::time::Instant::now() + PLAYLIST_WAIT_TIMEOUT;
+        while !tokio::fs::try_exists(&path).await.unwrap_or(false)
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(PLAYLIST_POLL_INTERVAL).await;
+        }
+    }
+
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => Bytes::from(data),
+        Err(err) => {
+            debug!(?err, name, "Failed to read HLS file");
+            return not_found();
+        }
+    };
+    let len = data.len();
+    let body = if req.method() == Method::HEAD {
+        Bytes::new()
+    } else {
+        data
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, len)
+        // The playlist is rewritten for every new segment
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body_full(body))
+}
+
+/// Serve the files in `dir` over HTTP. The bound port is reported with
+/// [`Event::SignallerStarted`], like the WHEP server does, so the play message is sent the same
+/// way for both kinds of streams.
+fn start_server(
+    sink_id: SinkId,
+    dir: PathBuf,
+    event_tx: crate::event_bus::EventSender,
+    rt_handle: &tokio::runtime::Handle,
+) -> anyhow::Result<tokio::sync::oneshot::Sender<()>> {
+    let listener =
+        std::net::TcpListener::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))?;
+    listener.set_nonblocking(true)?;
+    let bound_port = listener.local_addr()?.port();
+    let dir = Arc::new(dir);
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+
+    rt_handle.spawn(async move {
+        let listener = match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(?err, "Failed to register HLS server listener");
+                return;
+            }
+        };
+
+        debug!(bound_port, "HLS server started");
+        if let Err(err) = event_tx.send(Event::SignallerStarted {
+            sink_id,
+            bound_port_v4: bound_port,
+            bound_port_v6: bound_port,
+        }) {
+            error!(?err, "Failed to send event");
+        }
+
+        loop {
+            let (stream, addr) = tokio::select! {
+                conn = listener.accept() => match conn {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        error!(?err, "Accept error");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown_rx => break,
+            };
+
+            debug!(?addr, "Got HLS request connection");
+
+            let dir = Arc::clone(&dir);
+            tokio::spawn(async move {
+                let stream = hyper_util::rt::TokioIo::new(Box::pin(stream));
+                let server = hyper_util::server::conn::auto::Builder::new(
+                    hyper_util::rt::TokioExecutor::new(),
+                );
+                let conn = server.serve_connection(
+                    stream,
+                    hyper::service::service_fn(|req| {
+                        let dir = Arc::clone(&dir);
+                        async move { handle_request(req, &dir).await }
+                    }),
+                );
+
+                if let Err(err) = conn.await {
+                    error!(?err, "Failed to handle connection");
+                }
+            });
+        }
+
+        debug!("HLS server quit");
+    });
+
+    Ok(shutdown_tx)
+}
+
+fn add_video_src(
+    pipeline: &gst::Pipeline,
+    sink: &gst::Element,
+    src: VideoSource,
+    preview_sink: Option<gst_app::AppSink>,
+) -> anyhow::Result<()> {
+    let VideoSource::Source(appsrc) = src;
+    let appsrc: gst::Element = appsrc.upcast();
+    let convert = gst::ElementFactory::make("videoconvert").build()?;
+    let enc = gst::ElementFactory::make("x264enc")
+        .property_from_str("tune", "zerolatency")
+        .property_from_str("speed-preset", "ultrafast")
+        .property("bitrate", VIDEO_BITRATE_KBPS)
+        .property("key-int-max", KEYFRAME_INTERVAL)
+        .build()?;
+    let parse = gst::ElementFactory::make("h264parse").build()?;
+
+    pipeline.add_many([&appsrc, &convert, &enc, &parse])?;
+    let mut upstream = appsrc;
+
+    if let Some(appsink) = preview_sink {
+        let tee = gst::ElementFactory::make("tee").build()?;
+        let queue = gst::ElementFactory::make("queue").build()?;
+        pipeline.add_many([&tee, &queue])?;
+        gst::Element::link_many([&upstream, &tee, &queue])?;
+        crate::transmission::add_preview_branch(pipeline, &tee, appsink)?;
+        upstream = queue;
+    }
+
+    gst::Element::link_many([&upstream, &convert, &enc, &parse])?;
+    let sink_video_pad = sink
+        .request_pad_simple("video")
+        .ok_or(anyhow::anyhow!("Failed to request video pad from sink"))?;
+    parse
+        .static_pad("src")
+        .ok_or(anyhow::anyhow!("h264parse is missing src pad"))?
+        .link(&sink_video_pad)?;
+
+    Ok(())
+}
+
+fn add_audio_src(
+    pipeline: &gst::Pipeline,
+    sink: &gst::Element,
+    src: AudioSource,
+) -> anyhow::Result<()> {
+    let src: gst::Element = match src {
+        AudioSource::Source(appsrc) => appsrc.upcast(),
+        AudioSource::Microphone => gst::ElementFactory::make("openslessrc").build()?,
+    };
+    let convert = gst::ElementFactory::make("audioconvert").build()?;
+    let resample = gst::ElementFactory::make("audioresample").build()?;
+    let enc = gst::ElementFactory::make("avenc_aac").build()?;
+    let parse = gst::ElementFactory::make("aacparse").build()?;
+
+    pipeline.add_many([&src, &convert, &resample, &enc, &parse])?;
+    gst::Element::link_many([&src, &convert, &resample, &enc, &parse])?;
+
+    let sink_audio_pad = sink
+        .request_pad_simple("audio")
+        .ok_or(anyhow::anyhow!("Failed to request audio pad from sink"))?;
+    parse
+        .static_pad("src")
+        .ok_or(anyhow::anyhow!("aacparse is missing src pad"))?
+        .link(&sink_audio_pad)?;
+
+    Ok(())
+}
+
+/// Encodes the cast to H.264 and AAC, writes it as an HLS playlist with short segments to a
+/// directory and serves that directory to the receiver.
+#[derive(Debug)]
+pub struct HlsSink {
+    id: SinkId,
+    pipeline: gst::Pipeline,
+    shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+impl HlsSink {
+    /// `dir` is emptied and owned by the sink until it is shut down.
+    pub fn new(
+        source_config: SourceConfig,
+        event_tx: crate::event_bus::EventSender,
+        rt_handle: tokio::runtime::Handle,
+        dir: PathBuf,
+        preview_sink: Option<gst_app::AppSink>,
+    ) -> anyhow::Result<Self> {
+        // Segments left behind by an earlier cast would be served to the receiver
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        let pipeline = gst::Pipeline::new();
+        let sink = gst::ElementFactory::make("hlssink2")
+            .property(
+                "location",
+                dir.join(SEGMENT_PATTERN).to_string_lossy().as_ref(),
+            )
+            .property(
+                "playlist-location",
+                dir.join(PLAYLIST_NAME).to_string_lossy().as_ref(),
+            )
+            .property("target-duration", TARGET_DURATION)
+            .property("playlist-length", PLAYLIST_LENGTH)
+            .property("max-files", MAX_FILES)
+            .build()?;
+        pipeline.add(&sink)?;
+
+        match source_config {
+            SourceConfig::AudioVideo { video, audio } => {
+                add_video_src(&pipeline, &sink, video, preview_sink)?;
+                add_audio_src(&pipeline, &sink, audio)?;
+            }
+            SourceConfig::Video(src) => add_video_src(&pipeline, &sink, src, preview_sink)?,
+            SourceConfig::Audio(src) => add_audio_src(&pipeline, &sink, src)?,
+        }
+
+        pipeline.call_async(|pipeline| {
+            debug!("Starting HLS pipeline...");
+
+            if let Err(err) = pipeline.set_state(gst::State::Playing) {
+                error!("Failed to start pipeline: {err}");
+            } else {
+                debug!("HLS pipeline started");
+            }
+        });
+
+        crate::transmission::add_bus_handler(&pipeline, event_tx.clone(), rt_handle.clone())?;
+        let id = crate::next_sink_id();
+        let shutdown_tx = start_server(id, dir, event_tx, &rt_handle)?;
+
+        Ok(Self {
+            id,
+            pipeline,
+            shutdown_tx: Some(shutdown_tx),
+        })
+    }
+
+    /// Tag of the [`Event::SignallerStarted`] sent by this sink's server.
+    pub fn id(&self) -> SinkId {
+        self.id
+    }
+
+    pub fn get_play_msg(&self, addr: IpAddr, port: u16) -> (String, String) {
+        self.get_play_msg_for_host(&crate::transmission::addr_to_url_string(addr), port)
+    }
+
+    /// Like [`HlsSink::get_play_msg`] but with a hostname instead of an IP address.
+    pub fn get_play_msg_for_host(&self, host: &str, port: u16) -> (String, String) {
+        (
+            "application/vnd.apple.mpegurl".to_owned(),
+            format!("http://{host}:{port}/{PLAYLIST_NAME}"),
+        )
+    }
+
+    pub fn pause(&self) {
+        self.pipeline.call_async(|pipeline| {
+            if let Err(err) = pipeline.set_state(gst::State::Paused) {
+                error!("Failed to pause pipeline: {err}");
+            }
+        });
+    }
+
+    pub fn resume(&self) {
+        self.pipeline.call_async(|pipeline| {
+            if let Err(err) = pipeline.set_state(gst::State::Playing) {
+                error!("Failed to resume pipeline: {err}");
+            }
+        });
+    }
+
+    pub fn shutdown(&mut self) {
+        self.pipeline.call_async(|pipeline| {
+            if let Err(err) = pipeline.set_state(gst::State::Null) {
+                error!("Failed to stop pipeline: {err}");
+            }
+        });
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+}
+
+impl Drop for HlsSink {
+    fn drop(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+}
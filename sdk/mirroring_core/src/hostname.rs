@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use tracing::{debug, error};
+
+const SERVICE_TYPE: &str = "_fcast-sender._tcp.local.";
+
+/// Advertises `<name>.local` over mDNS so receivers can keep resolving this device after its
+/// address changes, e.g. when the DHCP lease is renewed mid session.
+#[derive(Debug)]
+pub struct HostnameAdvertiser {
+    daemon: mdns_sd::ServiceDaemon,
+    fullname: String,
+    hostname: String,
+}
+
+impl HostnameAdvertiser {
+    /// `name` must be a valid DNS label.
+    pub fn new(name: &str, port: u16) -> anyhow::Result<Self> {
+        let daemon = mdns_sd::ServiceDaemon::new()?;
+
+        let service = mdns_sd::ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &format!("{name}.local."),
+            (), // Auto
+            port,
+            None::<HashMap<String, String>>,
+        )?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_owned();
+
+        daemon.register(service)?;
+
+        let hostname = format!("{name}.local");
+        debug!(hostname, port, "Advertising hostname");
+
+        Ok(Self {
+            daemon,
+            fullname,
+            hostname,
+        })
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+}
+
+impl Drop for HostnameAdvertiser {
+    fn drop(&mut self) {
+        if let Err(err) = self.daemon.unregister(&self.fullname) {
+            error!(?err, "Failed to unregister hostname service");
+        }
+        if let Err(err) = self.daemon.shutdown() {
+            error!(?err, "Failed to shut down mDNS daemon");
+        }
+    }
+}
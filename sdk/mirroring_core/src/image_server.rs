@@ -0,0 +1,184 @@
+use bytes::Bytes;
+use fcast_sender_sdk::device;
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{Method, Request, Response, StatusCode, header};
+use parking_lot::Mutex;
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+use tokio::net::TcpListener;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+#[derive(Debug)]
+struct Image {
+    id: Uuid,
+    content_type: String,
+    data: Bytes,
+}
+
+pub fn content_type_from_path(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "heic" => "image/heic",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        _ => return None,
+    })
+}
+
+fn body_full(data: Bytes) -> BoxBody<Bytes, hyper::Error> {
+    http_body_util::Full::new(data)
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+fn handle_request(
+    req: Request<hyper::body::Incoming>,
+    image: &Mutex<Option<Image>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::http::Error> {
+    let image = image.lock();
+    match (req.method(), image.as_ref()) {
+        (&Method::GET | &Method::HEAD, Some(image))
+            if req.uri().path().trim_start_matches('/') == image.id.to_string() =>
+        {
+            let body = if req.method() == Method::HEAD {
+                Bytes::new()
+            } else {
+                image.data.clone()
+            };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, image.content_type.as_str())
+                .header(header::CONTENT_LENGTH, image.data.len())
+                .body(body_full(body))
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(body_full(Bytes::new())),
+    }
+}
+
+/// Serves a single still image at a time so it can be loaded by receivers. The image is kept in
+/// memory, so the source file can be removed once [`ImageServer::serve`] returns.
+#[derive(Debug)]
+pub struct ImageServer {
+    image: Arc<Mutex<Option<Image>>>,
+    bound_port: u16,
+    shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+impl ImageServer {
+    pub async fn new() -> anyhow::Result<Self> {
+        let listener =
+            TcpListener::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)).await?;
+        let bound_port = listener.local_addr()?.port();
+        let image = Arc::new(Mutex::new(None::<Image>));
+        let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+
+        tokio::spawn({
+            let image = Arc::clone(&image);
+            async move {
+                loop {
+                    let (stream, addr) = tokio::select! {
+                        conn = listener.accept() => match conn {
+                            Ok(conn) => conn,
+                            Err(err) => {
+                                error!(?err, "Accept error");
+                                continue;
+                            }
+                        },
+                        _ = &mut shutdown_rx => break,
+                    };
+
+                    debug!(?addr, "Got image request connection");
+
+                    let image = Arc::clone(&image);
+                    tokio::spawn(async move {
+                        let stream = hyper_util::rt::TokioIo::new(Box::pin(stream));
+                        let server = hyper_util::server::conn::auto::Builder::new(
+                            hyper_util::rt::TokioExecutor::new(),
+                        );
+                        let conn = server.serve_connection(
+                            stream,
+                            hyper::service::service_fn(|req| {
+                                let res = handle_request(req, &image);
+                                async move { res }
+                            }),
+                        );
+
+                        if let Err(err) = conn.await {
+                            error!(?err, "Failed to handle connection");
+                        }
+                    });
+                }
+
+                debug!("Image server quit");
+            }
+        });
+
+        debug!(bound_port, "Image server started");
+
+        Ok(Self {
+            image,
+            bound_port,
+            shutdown_tx: Some(shutdown_tx),
+        })
+    }
+
+    /// Replace the currently served image with the file at `path` and create a load request that
+    /// points the receiver at it. `content_type` is the type reported by whoever provided the file,
+    /// the type is guessed from the extension if it is missing or not an image type.
+    pub async fn serve(
+        &self,
+        path: &Path,
+        content_type: Option<&str>,
+        local_addr: IpAddr,
+    ) -> anyhow::Result<device::LoadRequest> {
+        let content_type = match content_type.filter(|ty| ty.starts_with("image/")) {
+            Some(content_type) => content_type.to_owned(),
+            None => content_type_from_path(path)
+                .ok_or(anyhow::anyhow!(
+                    "Unsupported image type: {}",
+                    path.display()
+                ))?
+                .to_owned(),
+        };
+        let data = Bytes::from(tokio::fs::read(path).await?);
+        let id = Uuid::new_v4();
+
+        debug!(?path, content_type, size = data.len(), "Serving image");
+
+        *self.image.lock() = Some(Image {
+            id,
+            content_type: content_type.clone(),
+            data,
+        });
+
+        Ok(device::LoadRequest::Image {
+            content_type,
+            url: format!(
+                "http://{}:{}/{id}",
+                crate::transmission::addr_to_url_string(local_addr),
+                self.bound_port
+            ),
+            metadata: None,
+            request_headers: None,
+        })
+    }
+}
+
+impl Drop for ImageServer {
+    fn drop(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+}
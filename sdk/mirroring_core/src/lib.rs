@@ -1,11 +1,18 @@
+use event_bus::EventSender;
 use fcast_sender_sdk::device::{self, DeviceInfo};
 #[cfg(any(target_os = "macos", target_os = "windows"))]
 use serde::Deserialize;
-use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
 
+pub mod event_bus;
+#[cfg(target_os = "android")]
+pub mod hls_sink;
+pub mod hostname;
+pub mod image_server;
+pub mod plane_copy;
 #[cfg(not(target_os = "android"))]
 pub mod preview;
+pub mod reachability;
 pub mod transmission;
 pub mod whep_signaller;
 #[cfg(not(target_os = "android"))]
@@ -16,7 +23,9 @@ pub enum AudioSource {
     #[cfg(target_os = "linux")]
     PulseVirtualSink,
     #[cfg(target_os = "android")]
-    None,
+    Source(gst_app::AppSrc),
+    #[cfg(target_os = "android")]
+    Microphone,
 }
 
 impl AudioSource {
@@ -30,7 +39,10 @@ impl AudioSource {
         #[cfg(target_os = "windows")]
         return "n/a".to_string();
         #[cfg(target_os = "android")]
-        return "n/a".to_string();
+        match self {
+            AudioSource::Source(_) => "Default".to_owned(),
+            AudioSource::Microphone => "Microphone".to_owned(),
+        }
     }
 }
 
@@ -90,13 +102,12 @@ impl VideoSource {
 
 #[derive(Debug)]
 pub enum SourceConfig {
-    #[cfg(not(target_os = "android"))]
+    /// Video and audio that are captured together and kept in sync by the pipeline clock
     AudioVideo {
         video: VideoSource,
         audio: AudioSource,
     },
     Video(VideoSource),
-    #[cfg(not(target_os = "android"))]
     Audio(AudioSource),
 }
 
@@ -115,7 +126,6 @@ pub enum DeviceEvent {
     VolumeChanged(f64),
     #[cfg(not(target_os = "android"))]
     TimeChanged(f64),
-    #[cfg(not(target_os = "android"))]
     PlaybackStateChanged(device::PlaybackState),
     #[cfg(not(target_os = "android"))]
     DurationChanged(f64),
@@ -128,7 +138,6 @@ pub enum DeviceEvent {
     // #[cfg(not(target_os = "android"))]
     // VolumeChanged(f64),
     // fn playback_error(&self, _message: String) {}
-    #[cfg(not(target_os = "android"))]
     PlaybackError(String),
     #[cfg(not(target_os = "android"))]
     Media(device::MediaEvent),
@@ -164,6 +173,26 @@ pub enum RootDirType {
     Music,
 }
 
+/// Kind of the default network as reported by Android's `ConnectivityManager`
+#[cfg(target_os = "android")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkType {
+    None,
+    Wifi,
+    Cellular,
+    Ethernet,
+    Other,
+}
+
+/// Identifies the cast sink that emitted an event, so events from a sink that was already
+/// replaced or shut down can be told apart from those of the current one
+pub type SinkId = u64;
+
+pub fn next_sink_id() -> SinkId {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+    NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
 #[cfg(any(target_os = "macos", target_os = "windows"))]
 #[derive(Debug, Deserialize)]
 pub struct Release {
@@ -179,6 +208,7 @@ pub enum Event {
     },
     ConnectToDevice(String),
     SignallerStarted {
+        sink_id: SinkId,
         bound_port_v4: u16,
         bound_port_v6: u16,
     },
@@ -274,27 +304,88 @@ pub enum Event {
     // #[cfg(target_os = "android")]
     // StartCast,
     #[cfg(target_os = "android")]
-    CaptureStarted,
+    CaptureStarted {
+        /// Whether device audio will be delivered alongside the video frames
+        with_audio: bool,
+    },
     #[cfg(target_os = "android")]
     CaptureStopped,
     #[cfg(target_os = "android")]
     CaptureCancelled,
     #[cfg(target_os = "android")]
     QrScanResult(String),
+    /// Reconnect to the receiver of a cast that was interrupted by the process being killed
+    #[cfg(target_os = "android")]
+    ResumeSession,
+    /// Change GStreamer debug thresholds, `threshold` uses the `GST_DEBUG` syntax and is ignored
+    /// when empty
+    #[cfg(target_os = "android")]
+    SetGstDebug {
+        threshold: String,
+        stream_logs: bool,
+    },
+    /// A receiver was discovered or its service was updated. `instance` is the full mDNS service
+    /// instance name, e.g. `Living room._fcast._tcp.local`, which identifies the receiver
+    /// independently of its display name in `device_info`.
+    #[cfg(target_os = "android")]
+    ServiceFound {
+        instance: String,
+        device_info: DeviceInfo,
+    },
+    /// The service with the given full instance name is gone
+    #[cfg(target_os = "android")]
+    ServiceLost(String),
+    /// A service instance has not been rediscovered since it was reported as lost
+    #[cfg(target_os = "android")]
+    DeviceRemovalSettled(String),
+    /// The default network changed, e.g. when roaming between Wi-Fi networks
+    #[cfg(target_os = "android")]
+    NetworkChanged {
+        network_type: NetworkType,
+        has_internet: bool,
+    },
+    /// Result of probing the addresses of a discovered device, `None` if none of them answered
+    #[cfg(target_os = "android")]
+    DeviceProbed {
+        instance: String,
+        result: Option<reachability::Probe>,
+    },
+    /// Result of probing the receiver that is being connected to, `device_id` is the id of its
+    /// [`DeviceHandler`]
+    #[cfg(target_os = "android")]
+    ConnectProbed {
+        device_id: usize,
+        result: Option<reachability::Probe>,
+    },
+    /// An image picked by the user, `content_type` is the MIME type reported by the picker
+    #[cfg(target_os = "android")]
+    CastImage {
+        path: std::path::PathBuf,
+        content_type: Option<String>,
+    },
+    /// A URL to open, or plain text to display, on the receiver
+    #[cfg(target_os = "android")]
+    CastText(String),
     #[cfg(target_os = "android")]
     StartCast {
         scale_width: u32,
         scale_height: u32,
         max_framerate: u32,
+        record: bool,
+        debug_overlay: bool,
+        latency_preset: transmission::LatencyPreset,
+        ice: transmission::IceSettings,
+        /// Stop the cast automatically once it has been running for this long
+        max_duration: Option<std::time::Duration>,
     },
 }
 
 pub struct Discoverer {
-    event_tx: UnboundedSender<Event>,
+    event_tx: EventSender,
 }
 
 impl Discoverer {
-    pub fn new(event_tx: UnboundedSender<Event>) -> Self {
+    pub fn new(event_tx: EventSender) -> Self {
         Self { event_tx }
     }
 
@@ -320,12 +411,12 @@ impl fcast_sender_sdk::DeviceDiscovererEventHandler for Discoverer {
 }
 
 pub struct DeviceHandler {
-    event_tx: UnboundedSender<Event>,
+    event_tx: EventSender,
     id: usize,
 }
 
 impl DeviceHandler {
-    pub fn new(id: usize, event_tx: UnboundedSender<Event>) -> Self {
+    pub fn new(id: usize, event_tx: EventSender) -> Self {
         Self { id, event_tx }
     }
 
@@ -351,9 +442,8 @@ impl device::DeviceEventHandler for DeviceHandler {
         self.send_event(DeviceEvent::TimeChanged(_time));
     }
 
-    fn playback_state_changed(&self, _state: device::PlaybackState) {
-        #[cfg(not(target_os = "android"))]
-        self.send_event(DeviceEvent::PlaybackStateChanged(_state));
+    fn playback_state_changed(&self, state: device::PlaybackState) {
+        self.send_event(DeviceEvent::PlaybackStateChanged(state));
     }
 
     fn duration_changed(&self, _duration: f64) {
@@ -377,5 +467,7 @@ impl device::DeviceEventHandler for DeviceHandler {
         self.send_event(DeviceEvent::Media(_event));
     }
 
-    fn playback_error(&self, _message: String) {}
+    fn playback_error(&self, message: String) {
+        self.send_event(DeviceEvent::PlaybackError(message));
+    }
 }
@@ -0,0 +1,97 @@
+/// Copy `rows` rows of `row_len` samples from a captured image plane into a video frame plane.
+///
+/// Rows of `src` are `src_row_stride` bytes apart and samples within a row are `pixel_stride`
+/// bytes apart, as described by `android.media.Image.Plane`. The last row of `src` is not padded
+/// to the row stride. Samples in `dest` are packed and its rows are `dest_stride` bytes apart.
+///
+/// Panics if either plane is too small for the given dimensions.
+pub fn copy_plane(
+    dest: &mut [u8],
+    dest_stride: usize,
+    src: &[u8],
+    (row_len, rows): (usize, usize),
+    src_row_stride: usize,
+    pixel_stride: usize,
+) {
+    if row_len == 0 || rows == 0 {
+        return;
+    }
+
+    // Same layout, copy the whole plane at once including the row padding
+    if pixel_stride == 1 && src_row_stride == dest_stride {
+        let len = (rows - 1) * dest_stride + row_len;
+        dest[..len].copy_from_slice(&src[..len]);
+        return;
+    }
+
+    let src_row_len = (row_len - 1) * pixel_stride + 1;
+    for (row_idx, dest_row) in dest.chunks_mut(dest_stride).take(rows).enumerate() {
+        let src_row = &src[row_idx * src_row_stride..][..src_row_len];
+        let dest_row = &mut dest_row[..row_len];
+        match pixel_stride {
+            1 => dest_row.copy_from_slice(src_row),
+            2 => copy_every_other(dest_row, src_row),
+            _ => {
+                for (dest, src) in dest_row
+                    .iter_mut()
+                    .zip(src_row.iter().step_by(pixel_stride))
+                {
+                    *dest = *src;
+                }
+            }
+        }
+    }
+}
+
+/// Copy every other byte of `src`, which is how the chroma planes of `YUV_420_888` images backed
+/// by semi-planar memory are laid out. `src` is `2 * dest.len() - 1` bytes long.
+fn copy_every_other(dest: &mut [u8], src: &[u8]) {
+    let last = dest.len() - 1;
+    // Iterating over whole pairs lets the compiler vectorize the loop, which `step_by` prevents
+    for (dest, pair) in dest[..last].iter_mut().zip(src.chunks_exact(2)) {
+        *dest = pair[0];
+    }
+    dest[last] = src[last * 2];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_packed_rows_with_different_strides() {
+        let src = [1, 2, 3, 0, 4, 5, 6];
+        let mut dest = [0; 8];
+        copy_plane(&mut dest, 4, &src, (3, 2), 4, 1);
+        assert_eq!(dest, [1, 2, 3, 0, 4, 5, 6, 0]);
+
+        let mut dest = [0; 6];
+        copy_plane(&mut dest, 3, &src, (3, 2), 4, 1);
+        assert_eq!(dest, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn copies_interleaved_samples() {
+        // Two rows of 3 samples with pixel stride 2 and a row stride of 8
+        let src = [1, 9, 2, 9, 3, 9, 9, 9, 4, 9, 5, 9, 6];
+        let mut dest = [0; 6];
+        copy_plane(&mut dest, 3, &src, (3, 2), 8, 2);
+        assert_eq!(dest, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn copies_samples_with_large_pixel_stride() {
+        let src = [1, 9, 9, 2, 9, 9, 3];
+        let mut dest = [0; 3];
+        copy_plane(&mut dest, 3, &src, (3, 1), 7, 3);
+        assert_eq!(dest, [1, 2, 3]);
+    }
+
+    #[test]
+    fn ignores_empty_planes() {
+        let mut dest = [7; 4];
+        copy_plane(&mut dest, 2, &[], (0, 2), 2, 1);
+        copy_plane(&mut dest, 2, &[], (2, 0), 2, 1);
+        assert_eq!(dest, [7; 4]);
+    }
+}
@@ -0,0 +1,88 @@
+use fcast_sender_sdk::IpAddr;
+use futures::StreamExt;
+use std::{
+    net::{SocketAddr, SocketAddrV6},
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    /// The address that answered first
+    pub addr: IpAddr,
+    /// Time it took to establish a TCP connection
+    pub rtt: Duration,
+}
+
+fn socket_addr(addr: &IpAddr, port: u16) -> SocketAddr {
+    match (std::net::IpAddr::from(addr), addr) {
+        (std::net::IpAddr::V6(v6), IpAddr::V6 { scope_id, .. }) => {
+            SocketAddr::V6(SocketAddrV6::new(v6, port, 0, *scope_id))
+        }
+        (ip, _) => SocketAddr::new(ip, port),
+    }
+}
+
+/// Open a TCP connection to every address concurrently and return the one that answered first.
+/// Connections are closed as soon as they are established.
+pub async fn probe(addrs: &[IpAddr], port: u16, timeout: Duration) -> Option<Probe> {
+    let mut probes = addrs
+        .iter()
+        .map(|addr| async move {
+            let start = Instant::now();
+            match tokio::time::timeout(timeout, TcpStream::connect(socket_addr(addr, port))).await {
+                Ok(Ok(_)) => Some(Probe {
+                    addr: *addr,
+                    rtt: start.elapsed(),
+                }),
+                _ => None,
+            }
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>();
+
+    while let Some(result) = probes.next().await {
+        if result.is_some() {
+            return result;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn test_probe_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let probe = probe(&[IpAddr::v4(127, 0, 0, 1)], port, TIMEOUT).await;
+        assert_eq!(
+            probe.map(|p| std::net::IpAddr::from(&p.addr)),
+            Some(std::net::IpAddr::from([127, 0, 0, 1]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        assert!(
+            probe(&[IpAddr::v4(127, 0, 0, 1)], port, TIMEOUT)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_no_addresses() {
+        assert!(probe(&[], 46899, TIMEOUT).await.is_none());
+    }
+}
@@ -6,6 +6,59 @@ use gst_rs_webrtc::signaller::Signallable;
 
 pub const ON_SERVER_STARTED_SIGNAL_NAME: &str = "on-server-started";
 
+/// Local ICE candidates to leave out of the SDP answers sent to receivers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CandidateFilter {
+    /// Drop candidates for the device's own addresses
+    pub drop_host: bool,
+    /// Drop host candidates that hide the address behind an mDNS `.local` name
+    pub drop_mdns: bool,
+    /// Only gather and offer relayed candidates, the sink needs a TURN server for this
+    pub relay_only: bool,
+}
+
+impl CandidateFilter {
+    /// Whether a candidate, given as the value of an SDP `candidate` attribute, is offered.
+    pub fn keeps(&self, candidate: &str) -> bool {
+        // foundation component transport priority address port typ <type> ...
+        let fields = candidate.split_whitespace().collect::<Vec<_>>();
+        let (Some(address), Some(&"typ"), Some(typ)) =
+            (fields.get(4), fields.get(6), fields.get(7))
+        else {
+            return true;
+        };
+
+        match *typ {
+            "host" if self.drop_host => false,
+            "host" if self.drop_mdns && address.ends_with(".local") => false,
+            "relay" => true,
+            _ => !self.relay_only,
+        }
+    }
+
+    fn apply(&self, sdp: &mut gst_sdp::SDPMessageRef) {
+        if *self == Self::default() {
+            return;
+        }
+
+        for media_idx in 0..sdp.medias_len() {
+            let Some(media) = sdp.media_mut(media_idx) else {
+                continue;
+            };
+            // Backwards so removing an attribute doesn't shift the ones left to check
+            for attr_idx in (0..media.attributes_len()).rev() {
+                let drop = media.attribute(attr_idx).is_some_and(|attr| {
+                    attr.key() == "candidate"
+                        && attr.value().is_some_and(|value| !self.keeps(value))
+                });
+                if drop && let Err(err) = media.remove_attribute(attr_idx) {
+                    tracing::error!(?err, "Failed to remove candidate from SDP answer");
+                }
+            }
+        }
+    }
+}
+
 mod imp {
     use bytes::Bytes;
     use gst::{glib, prelude::*, subclass::prelude::*};
@@ -42,6 +95,7 @@ mod imp {
         server_handle: Option<tokio::task::JoinHandle<()>>,
         sdp_answer: HashMap<String, mpsc::Sender<Option<gst_sdp::SDPMessage>>>,
         rt_handle: tokio::runtime::Handle,
+        candidate_filter: super::CandidateFilter,
     }
 
     impl Default for Settings {
@@ -53,6 +107,7 @@ mod imp {
                 server_handle: None,
                 sdp_answer: HashMap::new(),
                 rt_handle: tokio::runtime::Handle::try_current().unwrap(),
+                candidate_filter: super::CandidateFilter::default(),
             }
         }
     }
@@ -81,10 +136,18 @@ mod imp {
     }
 
     impl Signaller {
+        pub fn set_candidate_filter(&self, filter: super::CandidateFilter) {
+            self.settings.lock().candidate_filter = filter;
+        }
+
         pub fn on_webrtcbin_ready(&self) -> gst::glib::RustClosure {
             glib::closure!(|signaller: &super::WhepServerSignaller,
                             session_id: &str,
                             webrtcbin: &gst::Element| {
+                if signaller.imp().settings.lock().candidate_filter.relay_only {
+                    webrtcbin.set_property_from_str("ice-transport-policy", "relay");
+                }
+
                 webrtcbin.connect_notify(
                     Some("ice-gathering-state"),
                     glib::clone!(
@@ -109,7 +172,9 @@ mod imp {
                                             "local-description",
                                         )
                                     {
-                                        ans = Some(answer_desc.sdp().to_owned());
+                                        let mut sdp = answer_desc.sdp().to_owned();
+                                        settings.candidate_filter.apply(&mut sdp);
+                                        ans = Some(sdp);
                                     } else {
                                         ans = None;
                                     }
@@ -561,6 +626,13 @@ glib::wrapper! {
     pub struct WhepServerSignaller(ObjectSubclass<imp::Signaller>) @implements Signallable;
 }
 
+impl WhepServerSignaller {
+    pub fn set_candidate_filter(&self, filter: CandidateFilter) {
+        use gst::subclass::prelude::ObjectSubclassIsExt;
+        self.imp().set_candidate_filter(filter);
+    }
+}
+
 impl Default for WhepServerSignaller {
     fn default() -> Self {
         use gst::subclass::prelude::ObjectSubclassIsExt;
@@ -569,3 +641,52 @@ impl Default for WhepServerSignaller {
         sig
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "1 1 UDP 2015363327 192.168.1.2 51234 typ host";
+    const MDNS: &str = "2 1 UDP 2015363327 0c1f5d0e-7a1b.local 51235 typ host";
+    const RELAY: &str = "3 1 UDP 8331263 203.0.113.7 3478 typ relay raddr 0.0.0.0 rport 0";
+
+    #[test]
+    fn default_filter_keeps_everything() {
+        let filter = CandidateFilter::default();
+        assert!(filter.keeps(HOST));
+        assert!(filter.keeps(MDNS));
+        assert!(filter.keeps(RELAY));
+    }
+
+    #[test]
+    fn drops_mdns_host_candidates_only() {
+        let filter = CandidateFilter {
+            drop_mdns: true,
+            ..Default::default()
+        };
+        assert!(filter.keeps(HOST));
+        assert!(!filter.keeps(MDNS));
+        assert!(filter.keeps(RELAY));
+    }
+
+    #[test]
+    fn relay_only_keeps_relay_candidates() {
+        let filter = CandidateFilter {
+            relay_only: true,
+            ..Default::default()
+        };
+        assert!(!filter.keeps(HOST));
+        assert!(!filter.keeps(MDNS));
+        assert!(filter.keeps(RELAY));
+    }
+
+    #[test]
+    fn keeps_unparsable_candidates() {
+        let filter = CandidateFilter {
+            drop_host: true,
+            relay_only: true,
+            ..Default::default()
+        };
+        assert!(filter.keeps("1 1 UDP"));
+    }
+}
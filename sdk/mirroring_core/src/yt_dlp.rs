@@ -67,7 +67,7 @@ fn yt_dlp_command() -> tokio::process::Command {
 impl YtDlpSource {
     pub async fn try_get(
         url: &str,
-        event_tx: &tokio::sync::mpsc::UnboundedSender<crate::Event>,
+        event_tx: &crate::event_bus::EventSender,
         mut quit_rx: tokio::sync::oneshot::Receiver<()>,
     ) -> Result<()> {
         let mut cmd = yt_dlp_command();
@@ -5,20 +5,36 @@ import static android.opengl.GLES11Ext.GL_TEXTURE_EXTERNAL_OES;
 import static android.opengl.GLES20.*;
 import static android.opengl.GLES30.*;
 
+import android.Manifest;
 import android.app.Activity;
 import android.app.NativeActivity;
 import android.content.BroadcastReceiver;
 import android.content.Context;
 import android.content.Intent;
 import android.content.IntentFilter;
+import android.content.pm.PackageManager;
 import android.content.res.*;
+import android.graphics.ImageFormat;
 import android.graphics.SurfaceTexture;
+import android.hardware.DataSpace;
 import android.hardware.display.DisplayManager;
 import android.hardware.display.VirtualDisplay;
+import android.media.AudioAttributes;
+import android.media.AudioFormat;
+import android.media.AudioPlaybackCaptureConfiguration;
+import android.media.AudioRecord;
+import android.media.AudioTimestamp;
+import android.media.Image;
+import android.media.ImageReader;
 import android.media.projection.MediaProjection;
 import android.media.projection.MediaProjectionManager;
+import android.net.ConnectivityManager;
+import android.net.Network;
+import android.net.NetworkCapabilities;
+import android.net.Uri;
 import android.net.nsd.NsdManager;
 import android.net.nsd.NsdServiceInfo;
+import android.net.wifi.WifiManager;
 import android.opengl.EGL14;
 import android.opengl.EGLConfig;
 import android.opengl.EGLContext;
@@ -28,6 +44,7 @@ import android.os.*;
 import android.util.DisplayMetrics;
 import android.util.Log;
 import android.view.*;
+import android.webkit.MimeTypeMap;
 
 import androidx.annotation.NonNull;
 import androidx.localbroadcastmanager.content.LocalBroadcastManager;
@@ -35,7 +52,15 @@ import androidx.localbroadcastmanager.content.LocalBroadcastManager;
 import com.journeyapps.barcodescanner.ScanOptions;
 
 import org.freedesktop.gstreamer.GStreamer;
-
+import org.json.JSONArray;
+import org.json.JSONException;
+import org.json.JSONObject;
+
+import java.io.File;
+import java.io.FileOutputStream;
+import java.io.IOException;
+import java.io.InputStream;
+import java.io.OutputStream;
 import java.net.Inet6Address;
 import java.net.InetAddress;
 import java.nio.ByteBuffer;
@@ -59,10 +84,12 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
 
     private static ByteBuffer addrConvert(InetAddress addr) {
         byte[] addrB = addr.getAddress();
-        ByteBuffer buffer = ByteBuffer.allocateDirect(addrB.length);
+        boolean isV6 = addr instanceof Inet6Address;
+        // IPv6 addresses are followed by their scope ID
+        ByteBuffer buffer = ByteBuffer.allocateDirect(addrB.length + (isV6 ? 4 : 0));
         buffer.put(addrB);
 
-        if (addr.getClass() == Inet6Address.class) {
+        if (isV6) {
             int scopeId = ((Inet6Address) addr).getScopeId();
             buffer.order(ByteOrder.LITTLE_ENDIAN).putInt(scopeId);
         }
@@ -70,6 +97,16 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
         return buffer;
     }
 
+    /**
+     * Full mDNS instance name, e.g. {@code Living room._fcast._tcp.local}. Receivers are keyed by
+     * it because the service name alone is only their display name.
+     */
+    private static String instanceName(NsdServiceInfo serviceInfo) {
+        // Depending on the callback the type comes with a leading or a trailing dot
+        String type = serviceInfo.getServiceType().replaceAll("^\\.+|\\.+$", "");
+        return serviceInfo.getServiceName() + "." + type + ".local";
+    }
+
     @Override
     public void onStartDiscoveryFailed(String serviceType, int errorCode) {
         Log.e(TAG, "Failed to start discovery errorCode=" + errorCode);
@@ -104,7 +141,7 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
             }
         }
         List<ByteBuffer> addrsB = addrs.stream().map(FCastDiscoveryListener::addrConvert).collect(Collectors.toList());
-        serviceFound(serviceInfo.getServiceName(), addrsB, serviceInfo.getPort());
+        serviceFound(instanceName(serviceInfo), serviceInfo.getServiceName(), addrsB, serviceInfo.getPort());
 
         if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.UPSIDE_DOWN_CAKE) {
             nsdManager.registerServiceInfoCallback(serviceInfo, Runnable::run, new NsdManager.ServiceInfoCallback() {
@@ -114,12 +151,12 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
 
                 @Override
                 public void onServiceUpdated(@NonNull NsdServiceInfo serviceInfo) {
-                    serviceFound(serviceInfo.getServiceName(), serviceInfo.getHostAddresses().stream().map(FCastDiscoveryListener::addrConvert).collect(Collectors.toList()), serviceInfo.getPort());
+                    serviceFound(instanceName(serviceInfo), serviceInfo.getServiceName(), serviceInfo.getHostAddresses().stream().map(FCastDiscoveryListener::addrConvert).collect(Collectors.toList()), serviceInfo.getPort());
                 }
 
                 @Override
                 public void onServiceLost() {
-                    serviceLost(serviceInfo.getServiceName());
+                    serviceLost(instanceName(serviceInfo));
                 }
 
                 @Override
@@ -138,7 +175,7 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
                     Log.i(TAG, "Service resolved serviceInfo=" + serviceInfo);
                     InetAddress addr = serviceInfo.getHost();
                     if (addr != null) {
-                        serviceFound(serviceInfo.getServiceName(), List.of(addrConvert(addr)), serviceInfo.getPort());
+                        serviceFound(instanceName(serviceInfo), serviceInfo.getServiceName(), List.of(addrConvert(addr)), serviceInfo.getPort());
                     }
                 }
             });
@@ -148,18 +185,37 @@ class FCastDiscoveryListener implements NsdManager.DiscoveryListener {
     @Override
     public void onServiceLost(NsdServiceInfo serviceInfo) {
         Log.i(TAG, "Service lost serviceInfo=" + serviceInfo);
-        serviceLost(serviceInfo.getServiceName());
+        serviceLost(instanceName(serviceInfo));
     }
 
-    private native void serviceFound(String name, List<ByteBuffer> addrs, int port);
+    private native void serviceFound(String instance, String name, List<ByteBuffer> addrs, int port);
 
-    private native void serviceLost(String name);
+    private native void serviceLost(String instance);
 }
 
 class Discoverer {
+    private static final String TAG = "Discoverer";
+    private final NsdManager nsdManager;
+    private FCastDiscoveryListener listener;
+
     public Discoverer(Context context) {
-        NsdManager nsdManager = (NsdManager) context.getSystemService(Context.NSD_SERVICE);
-        nsdManager.discoverServices("_fcast._tcp", NsdManager.PROTOCOL_DNS_SD, new FCastDiscoveryListener(nsdManager));
+        nsdManager = (NsdManager) context.getSystemService(Context.NSD_SERVICE);
+        start();
+    }
+
+    private void start() {
+        listener = new FCastDiscoveryListener(nsdManager);
+        nsdManager.discoverServices("_fcast._tcp", NsdManager.PROTOCOL_DNS_SD, listener);
+    }
+
+    /** Start over, e.g. after switching networks where the old discovery will not find anything. */
+    public void restart() {
+        try {
+            nsdManager.stopServiceDiscovery(listener);
+        } catch (IllegalArgumentException e) {
+            Log.w(TAG, "Discovery was not running", e);
+        }
+        start();
     }
 }
 
@@ -204,7 +260,22 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
     public static final String ACTION_MEDIA_PROJECTION_STARTED = "org.fcast.android.sender.ACTION_MEDIA_PROJECTION_STARTED";
     private static final int REQUEST_CODE = 1;
     private static final int QR_SCAN_REQUEST_CODE = 2;
+    private static final int IMAGE_PICK_REQUEST_CODE = 3;
+    private static final int AUDIO_PERMISSION_REQUEST_CODE = 4;
     private static final String TAG = "MainActivity";
+    private static final String GST_LOG_TAG = "GStreamer";
+    /** How long the virtual display gets to deliver its first image before falling back to GL */
+    private static final long IMAGE_READER_TIMEOUT_MS = 2000;
+    private static final int AUDIO_SAMPLE_RATE = 48000;
+    private static final int AUDIO_CHANNELS = 2;
+    /** 10 ms of 16-bit PCM */
+    private static final int AUDIO_CHUNK_SIZE = AUDIO_SAMPLE_RATE / 100 * AUDIO_CHANNELS * 2;
+    /**
+     * Boolean extra that starts the native casting engine without its UI, so automation can drive
+     * it through {@link #nativeConnectReceiver}, {@link #nativeStartCast} and
+     * {@link #nativeStopCast} and follow it through {@link #onNativeEvent}.
+     */
+    public static final String EXTRA_HEADLESS = "org.fcast.android.sender.EXTRA_HEADLESS";
 
     static {
         System.loadLibrary("gstreamer_android");
@@ -220,6 +291,15 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
     private EGLDisplay eglDisplay = EGL14.EGL_NO_DISPLAY;
     private EGLSurface eglSurface = EGL14.EGL_NO_SURFACE;
     private Surface surface;
+    private ImageReader imageReader;
+    /** Set once the virtual display could not render into a YUV image reader, see {@link #setupCapture} */
+    private boolean useGlCapture = false;
+    /** Cleared once the virtual display could not render into a 10-bit image reader */
+    private boolean useP010Capture = true;
+    private boolean imageReceived = false;
+    private AudioRecord audioRecord;
+    private Thread audioThread;
+    private final AtomicBoolean audioCaptureRunning = new AtomicBoolean(false);
     private HandlerThread glThread;
     private Handler glHandler;
     private DisplayManager displayManager;
@@ -227,6 +307,26 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
     private int userMaxWidth = 1920;
     private int userMaxHeight = 1080;
     private int userMaxFps = 30;
+    private WifiManager.MulticastLock multicastLock;
+    private Discoverer discoverer;
+
+    // Must match the mapping in nativeNetworkChanged
+    private static final int NETWORK_TYPE_NONE = 0;
+    private static final int NETWORK_TYPE_WIFI = 1;
+    private static final int NETWORK_TYPE_CELLULAR = 2;
+    private static final int NETWORK_TYPE_ETHERNET = 3;
+    private static final int NETWORK_TYPE_OTHER = 4;
+
+    private static int networkType(NetworkCapabilities capabilities) {
+        if (capabilities.hasTransport(NetworkCapabilities.TRANSPORT_WIFI)) {
+            return NETWORK_TYPE_WIFI;
+        } else if (capabilities.hasTransport(NetworkCapabilities.TRANSPORT_CELLULAR)) {
+            return NETWORK_TYPE_CELLULAR;
+        } else if (capabilities.hasTransport(NetworkCapabilities.TRANSPORT_ETHERNET)) {
+            return NETWORK_TYPE_ETHERNET;
+        }
+        return NETWORK_TYPE_OTHER;
+    }
 
     @Override
     public void onDisplayAdded(int displayId) { }
@@ -265,7 +365,7 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
             android.util.DisplayMetrics m = new android.util.DisplayMetrics();
             this.getWindowManager().getDefaultDisplay().getMetrics(m);
             cleanupCapture(false);
-            glHandler.post(() -> setupGles(new Dimensions(userMaxWidth, userMaxHeight), newDims));
+            glHandler.post(() -> setupCapture(new Dimensions(userMaxWidth, userMaxHeight), newDims));
         }
     }
 
@@ -286,6 +386,8 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
 
     @Override
     protected void onCreate(Bundle savedInstanceState) {
+        // Must be set before the native main function is started by NativeActivity
+        nativeSetHeadless(getIntent().getBooleanExtra(EXTRA_HEADLESS, false));
         super.onCreate(savedInstanceState);
 
         try {
@@ -295,7 +397,27 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
             finish();
         }
 
-        Discoverer discoverer = new Discoverer(this);
+        discoverer = new Discoverer(this);
+
+        ConnectivityManager connectivityManager = getSystemService(ConnectivityManager.class);
+        connectivityManager.registerDefaultNetworkCallback(new ConnectivityManager.NetworkCallback() {
+            @Override
+            public void onCapabilitiesChanged(@NonNull Network network, @NonNull NetworkCapabilities capabilities) {
+                nativeNetworkChanged(networkType(capabilities),
+                        capabilities.hasCapability(NetworkCapabilities.NET_CAPABILITY_VALIDATED));
+            }
+
+            @Override
+            public void onLost(@NonNull Network network) {
+                nativeNetworkChanged(NETWORK_TYPE_NONE, false);
+            }
+        });
+
+        // Needed for the native mDNS responder to receive queries for our hostname
+        WifiManager wifiManager = (WifiManager) getApplicationContext().getSystemService(Context.WIFI_SERVICE);
+        multicastLock = wifiManager.createMulticastLock("fcast-sender-mdns");
+        multicastLock.setReferenceCounted(false);
+        multicastLock.acquire();
 
         projectionCallback = new ProjectionCallback();
         mediaProjectionManager = (MediaProjectionManager) getSystemService(MEDIA_PROJECTION_SERVICE);
@@ -511,10 +633,15 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
             glBindFramebuffer(GL_FRAMEBUFFER, 0);
         }
 
+        // glReadPixels pads rows to GL_PACK_ALIGNMENT, which defaults to 4
+        int rowStride() {
+            return (dims.width + 3) & ~3;
+        }
+
         private void readPixels() {
             glBindFramebuffer(GL_FRAMEBUFFER, fboId);
-            if (buf.capacity() < dims.width * dims.height) {
-                buf = ByteBuffer.allocateDirect(dims.width * dims.height);
+            if (buf.capacity() < rowStride() * dims.height) {
+                buf = ByteBuffer.allocateDirect(rowStride() * dims.height);
             }
             buf.position(0);
             glReadPixels(0, 0, dims.width, dims.height, GL_RED, GL_UNSIGNED_BYTE, buf);
@@ -583,7 +710,11 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
 
         EGL14.eglMakeCurrent(eglDisplay, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_CONTEXT);
 
-        nativeProcessFrame(downscaledDims.width, downscaledDims.height, yFramebuffer.buf, uFramebuffer.buf, vFramebuffer.buf);
+        nativeProcessFrame(downscaledDims.width, downscaledDims.height,
+                yFramebuffer.buf, yFramebuffer.rowStride(),
+                uFramebuffer.buf, uFramebuffer.rowStride(), 1,
+                vFramebuffer.buf, vFramebuffer.rowStride(), 1,
+                surfaceTexture.getTimestamp());
 
         lastFrameSent = now;
     }
@@ -642,6 +773,7 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         srcDims = Objects.requireNonNullElseGet(suggestedDims, () -> new Dimensions(srcWidth, srcHeight));
         downscaledDims = srcDims.scale(maxDims);
         uvDims = new Dimensions(downscaledDims.width / 2, downscaledDims.height / 2);
+        nativeFrameSizeChanged(downscaledDims.width, downscaledDims.height);
 
         int[] surfaceAttribs = {EGL14.EGL_WIDTH, downscaledDims.width, EGL14.EGL_HEIGHT, downscaledDims.height, EGL14.EGL_NONE};
         Log.d(TAG, "Creating EGL surface");
@@ -695,20 +827,165 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         }, glHandler);
 
         surface = new Surface(surfaceTexture);
+        attachVirtualDisplay(surface, srcDims, srcDensity);
+
+        EGL14.eglMakeCurrent(eglDisplay, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_CONTEXT);
+
+        shouldCapture.set(true);
 
+        captureLock.unlock();
+    }
+
+    private void attachVirtualDisplay(Surface target, Dimensions dims, int density) {
         if (virtualDisplay == null) {
-            virtualDisplay = mediaProjection.createVirtualDisplay("ScreenCapture", srcDims.width, srcDims.height, srcDensity, DisplayManager.VIRTUAL_DISPLAY_FLAG_AUTO_MIRROR | DisplayManager.VIRTUAL_DISPLAY_FLAG_PUBLIC | DisplayManager.VIRTUAL_DISPLAY_FLAG_PRESENTATION, surface, null, null);
+            virtualDisplay = mediaProjection.createVirtualDisplay("ScreenCapture", dims.width, dims.height, density, DisplayManager.VIRTUAL_DISPLAY_FLAG_AUTO_MIRROR | DisplayManager.VIRTUAL_DISPLAY_FLAG_PUBLIC | DisplayManager.VIRTUAL_DISPLAY_FLAG_PRESENTATION, target, null, null);
         } else {
             Log.d(TAG, "Reusing virtual display");
-            virtualDisplay.setSurface(surface);
-            virtualDisplay.resize(srcDims.width, srcDims.height, srcDensity);
+            virtualDisplay.setSurface(target);
+            virtualDisplay.resize(dims.width, dims.height, density);
         }
+    }
 
-        EGL14.eglMakeCurrent(eglDisplay, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_CONTEXT);
+    /**
+     * Capture straight into YUV images where the device supports it, which skips the GL
+     * conversion and the extra copy of the frame it needs. Falls back to {@link #setupGles} for
+     * the rest of the process otherwise.
+     */
+    private void setupCapture(Dimensions maxDims, Dimensions suggestedDims) {
+        if (!useGlCapture && setupImageReader(maxDims, suggestedDims)) {
+            return;
+        }
+        setupGles(maxDims, suggestedDims);
+    }
 
-        shouldCapture.set(true);
+    private boolean setupImageReader(Dimensions maxDims, Dimensions suggestedDims) {
+        captureLock.lock();
+        try {
+            DisplayMetrics metrics = getResources().getDisplayMetrics();
+            srcDims = Objects.requireNonNullElseGet(suggestedDims, () -> new Dimensions(metrics.widthPixels, metrics.heightPixels));
+            downscaledDims = srcDims.scale(maxDims);
+            nativeFrameSizeChanged(downscaledDims.width, downscaledDims.height);
+
+            // Keep the 10 bits of HDR content instead of letting the compositor tone map it
+            int format = useP010Capture && isHdrDisplay() ? ImageFormat.YCBCR_P010 : ImageFormat.YUV_420_888;
+            ImageReader reader;
+            try {
+                reader = ImageReader.newInstance(downscaledDims.width, downscaledDims.height, format, 2);
+            } catch (IllegalArgumentException | UnsupportedOperationException e) {
+                if (format == ImageFormat.YCBCR_P010) {
+                    Log.w(TAG, "10-bit image reader capture is not supported", e);
+                    useP010Capture = false;
+                    return setupImageReader(maxDims, suggestedDims);
+                }
+                Log.w(TAG, "Image reader capture is not supported", e);
+                return false;
+            }
 
-        captureLock.unlock();
+            Log.d(TAG, "Capturing with an image reader format=" + format + " dims=" + downscaledDims.width + "x" + downscaledDims.height);
+            imageReader = reader;
+            imageReceived = false;
+            reader.setOnImageAvailableListener(this::onImageAvailable, glHandler);
+            // The virtual display scales the screen down itself
+            attachVirtualDisplay(reader.getSurface(), downscaledDims, metrics.densityDpi);
+            shouldCapture.set(true);
+
+            // Some devices never render into YUV buffers
+            glHandler.postDelayed(() -> {
+                if (imageReader == reader && !imageReceived && shouldCapture.get()) {
+                    Log.w(TAG, "Image reader format=" + reader.getImageFormat() + " did not receive any images");
+                    fallBackFromImageReader(reader.getImageFormat());
+                }
+            }, IMAGE_READER_TIMEOUT_MS);
+
+            return true;
+        } finally {
+            captureLock.unlock();
+        }
+    }
+
+    private boolean isHdrDisplay() {
+        if (Build.VERSION.SDK_INT < Build.VERSION_CODES.TIRAMISU) {
+            return false;
+        }
+        Display display = getDisplay();
+        return display != null && display.isHdr();
+    }
+
+    /** 10-bit capture falls back to 8-bit capture, which falls back to GL capture. */
+    private void fallBackFromImageReader(int failedFormat) {
+        if (failedFormat == ImageFormat.YCBCR_P010) {
+            useP010Capture = false;
+        } else {
+            useGlCapture = true;
+        }
+        Dimensions dims = srcDims;
+        cleanupCapture(false);
+        glHandler.post(() -> setupCapture(new Dimensions(userMaxWidth, userMaxHeight), dims));
+    }
+
+    private void onImageAvailable(ImageReader reader) {
+        Image image = reader.acquireLatestImage();
+        if (image == null) {
+            return;
+        }
+
+        try {
+            imageReceived = true;
+            if (!shouldCapture.get()) {
+                return;
+            }
+
+            Instant now = Instant.now();
+            // Drop early frames
+            if (Duration.between(lastFrameSent, now).compareTo(Duration.ofMillis(1000 / userMaxFps)) < 0) {
+                return;
+            }
+
+            if (!processImage(image)) {
+                Log.w(TAG, "Unsupported image format=" + image.getFormat());
+                fallBackFromImageReader(reader.getImageFormat());
+                return;
+            }
+
+            lastFrameSent = now;
+        } finally {
+            image.close();
+        }
+    }
+
+    /** Returns false if the image's format can't be handed to native code. */
+    private boolean processImage(Image image) {
+        Image.Plane[] planes = image.getPlanes();
+        if (planes.length != 3) {
+            return false;
+        }
+
+        Image.Plane y = planes[0];
+        Image.Plane u = planes[1];
+        Image.Plane v = planes[2];
+        if (image.getFormat() == ImageFormat.YCBCR_P010) {
+            boolean hdr = Build.VERSION.SDK_INT >= Build.VERSION_CODES.TIRAMISU
+                    && DataSpace.getTransfer(image.getDataSpace()) == DataSpace.TRANSFER_ST2084;
+            nativeProcessP010Frame(image.getWidth(), image.getHeight(), hdr,
+                    y.getBuffer(), y.getRowStride(),
+                    u.getBuffer(), v.getBuffer(), u.getRowStride(),
+                    image.getTimestamp());
+        } else if (image.getFormat() != ImageFormat.YUV_420_888) {
+            return false;
+        } else if (u.getPixelStride() == 2 && v.getPixelStride() == 2) {
+            // NV12 or NV21 memory, native code tells them apart by the buffer addresses
+            nativeProcessSemiPlanarFrame(image.getWidth(), image.getHeight(),
+                    y.getBuffer(), y.getRowStride(),
+                    u.getBuffer(), v.getBuffer(), u.getRowStride(),
+                    image.getTimestamp());
+        } else {
+            nativeProcessFrame(image.getWidth(), image.getHeight(),
+                    y.getBuffer(), y.getRowStride(),
+                    u.getBuffer(), u.getRowStride(), u.getPixelStride(),
+                    v.getBuffer(), v.getRowStride(), v.getPixelStride(),
+                    image.getTimestamp());
+        }
+        return true;
     }
 
     // Called from native code
@@ -717,52 +994,179 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         userMaxWidth = scaleWidth;
         userMaxHeight = scaleHeight;
         userMaxFps = maxFramerate;
+        // Device audio can only be captured with the record permission, ask for it up front so
+        // the prompt doesn't show up on top of the cast
+        if (Build.VERSION.SDK_INT >= Build.VERSION_CODES.Q
+                && checkSelfPermission(Manifest.permission.RECORD_AUDIO) != PackageManager.PERMISSION_GRANTED) {
+            requestPermissions(new String[]{Manifest.permission.RECORD_AUDIO}, AUDIO_PERMISSION_REQUEST_CODE);
+            return;
+        }
+        requestMediaProjection();
+    }
+
+    private void requestMediaProjection() {
         MediaProjectionManager projectionManager = (MediaProjectionManager) getSystemService(Context.MEDIA_PROJECTION_SERVICE);
         startActivityForResult(projectionManager.createScreenCaptureIntent(), REQUEST_CODE);
     }
 
-    private void cleanupCapture(boolean shouldEmitStopSignal) {
-        if (!shouldCapture.get()) {
-            // Already stopped
-            return;
+    @Override
+    public void onRequestPermissionsResult(int requestCode, @NonNull String[] permissions, @NonNull int[] grantResults) {
+        super.onRequestPermissionsResult(requestCode, permissions, grantResults);
+        if (requestCode == AUDIO_PERMISSION_REQUEST_CODE) {
+            // Without the permission the screen is still cast, just without audio
+            requestMediaProjection();
         }
+    }
 
-        Log.d(TAG, "Stopping capture");
+    /**
+     * Starts capturing the audio played by other apps into {@link #nativeProcessAudioFrame}.
+     * Returns false if the device, the permissions or the playing apps don't allow it.
+     */
+    private boolean startAudioCapture() {
+        if (Build.VERSION.SDK_INT < Build.VERSION_CODES.Q
+                || checkSelfPermission(Manifest.permission.RECORD_AUDIO) != PackageManager.PERMISSION_GRANTED) {
+            Log.i(TAG, "Audio playback capture is not available");
+            return false;
+        }
 
-        shouldCapture.set(false);
+        AudioPlaybackCaptureConfiguration config = new AudioPlaybackCaptureConfiguration.Builder(mediaProjection)
+                .addMatchingUsage(AudioAttributes.USAGE_MEDIA)
+                .addMatchingUsage(AudioAttributes.USAGE_GAME)
+                .addMatchingUsage(AudioAttributes.USAGE_UNKNOWN)
+                .build();
+        AudioFormat format = new AudioFormat.Builder()
+                .setEncoding(AudioFormat.ENCODING_PCM_16BIT)
+                .setSampleRate(AUDIO_SAMPLE_RATE)
+                .setChannelMask(AudioFormat.CHANNEL_IN_STEREO)
+                .build();
+        int minBufferSize = AudioRecord.getMinBufferSize(AUDIO_SAMPLE_RATE, AudioFormat.CHANNEL_IN_STEREO, AudioFormat.ENCODING_PCM_16BIT);
+
+        AudioRecord record;
+        try {
+            record = new AudioRecord.Builder()
+                    .setAudioFormat(format)
+                    .setBufferSizeInBytes(Math.max(minBufferSize, AUDIO_CHUNK_SIZE * 4))
+                    .setAudioPlaybackCaptureConfig(config)
+                    .build();
+            record.startRecording();
+        } catch (UnsupportedOperationException | IllegalStateException | SecurityException e) {
+            Log.e(TAG, "Failed to start audio playback capture", e);
+            return false;
+        }
 
-        glHandler.post(() -> {
-            synchronized (captureLock) {
-                if (!EGL14.eglMakeCurrent(eglDisplay, eglSurface, eglSurface, eglContext)) {
-                    Log.e(TAG, "EGL make current failed: " + EGL14.eglGetError());
-                    return;
+        audioRecord = record;
+        audioCaptureRunning.set(true);
+        audioThread = new Thread(() -> readAudio(record), "AudioCaptureThread");
+        audioThread.start();
+        Log.d(TAG, "Audio playback capture started");
+        return true;
+    }
+
+    private void readAudio(AudioRecord record) {
+        ByteBuffer buffer = ByteBuffer.allocateDirect(AUDIO_CHUNK_SIZE);
+        AudioTimestamp timestamp = new AudioTimestamp();
+        int frameSize = AUDIO_CHANNELS * 2;
+        long framesRead = 0;
+
+        while (audioCaptureRunning.get()) {
+            buffer.clear();
+            int read = record.read(buffer, AUDIO_CHUNK_SIZE, AudioRecord.READ_BLOCKING);
+            if (read <= 0) {
+                if (read < 0) {
+                    Log.e(TAG, "Failed to read captured audio: " + read);
+                    break;
                 }
+                continue;
+            }
 
-                glDeleteProgram(yProg.program);
-                glDeleteProgram(uProg.program);
-                glDeleteProgram(vProg.program);
+            // Extrapolate the capture time of the first frame of the chunk from the latest
+            // timestamp the audio HAL reported
+            long timestampNs = 0;
+            if (record.getTimestamp(timestamp, AudioTimestamp.TIMEBASE_MONOTONIC) == AudioRecord.SUCCESS) {
+                timestampNs = timestamp.nanoTime + (framesRead - timestamp.framePosition) * 1_000_000_000L / AUDIO_SAMPLE_RATE;
+            }
 
-                yProg = null;
-                uProg = null;
-                vProg = null;
+            nativeProcessAudioFrame(buffer, read, AUDIO_SAMPLE_RATE, AUDIO_CHANNELS, Math.max(timestampNs, 0));
+            framesRead += read / frameSize;
+        }
+    }
 
-                glDeleteFramebuffers(3, new int[]{yFramebuffer.fboId, uFramebuffer.fboId, vFramebuffer.fboId}, 0);
+    private void stopAudioCapture() {
+        if (!audioCaptureRunning.getAndSet(false)) {
+            return;
+        }
+
+        audioRecord.stop();
+        try {
+            audioThread.join();
+        } catch (InterruptedException e) {
+            Log.w(TAG, "Interrupted while waiting for the audio thread", e);
+        }
+        audioRecord.release();
+        audioRecord = null;
+        audioThread = null;
+        Log.d(TAG, "Audio playback capture stopped");
+    }
 
-                glDeleteTextures(4, new int[]{oesTexId, yFramebuffer.texId, uFramebuffer.texId, vFramebuffer.texId}, 0);
+    /** Releases the GL resources of {@link #setupGles}, returns false if the context could not be made current. */
+    private boolean releaseGles() {
+        if (!EGL14.eglMakeCurrent(eglDisplay, eglSurface, eglSurface, eglContext)) {
+            Log.e(TAG, "EGL make current failed: " + EGL14.eglGetError());
+            return false;
+        }
 
-                yFramebuffer = null;
-                uFramebuffer = null;
-                vFramebuffer = null;
+        glDeleteProgram(yProg.program);
+        glDeleteProgram(uProg.program);
+        glDeleteProgram(vProg.program);
 
-                EGL14.eglMakeCurrent(eglDisplay, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_CONTEXT);
+        yProg = null;
+        uProg = null;
+        vProg = null;
 
-                EGL14.eglDestroySurface(eglDisplay, eglSurface);
-                eglSurface = EGL14.EGL_NO_SURFACE;
+        glDeleteFramebuffers(3, new int[]{yFramebuffer.fboId, uFramebuffer.fboId, vFramebuffer.fboId}, 0);
 
-                EGL14.eglDestroyContext(eglDisplay, eglContext);
-                eglContext = EGL14.EGL_NO_CONTEXT;
+        glDeleteTextures(4, new int[]{oesTexId, yFramebuffer.texId, uFramebuffer.texId, vFramebuffer.texId}, 0);
 
-                eglDisplay = EGL14.EGL_NO_DISPLAY;
+        yFramebuffer = null;
+        uFramebuffer = null;
+        vFramebuffer = null;
+
+        EGL14.eglMakeCurrent(eglDisplay, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_SURFACE, EGL14.EGL_NO_CONTEXT);
+
+        EGL14.eglDestroySurface(eglDisplay, eglSurface);
+        eglSurface = EGL14.EGL_NO_SURFACE;
+
+        EGL14.eglDestroyContext(eglDisplay, eglContext);
+        eglContext = EGL14.EGL_NO_CONTEXT;
+
+        eglDisplay = EGL14.EGL_NO_DISPLAY;
+
+        return true;
+    }
+
+    private void cleanupCapture(boolean shouldEmitStopSignal) {
+        if (shouldEmitStopSignal) {
+            stopAudioCapture();
+        }
+
+        if (!shouldCapture.get()) {
+            // Already stopped
+            return;
+        }
+
+        Log.d(TAG, "Stopping capture");
+
+        shouldCapture.set(false);
+
+        glHandler.post(() -> {
+            synchronized (captureLock) {
+                if (imageReader != null) {
+                    imageReader.close();
+                    imageReader = null;
+                    Log.d(TAG, "Image reader closed");
+                } else if (!releaseGles()) {
+                    return;
+                }
 
                 if (shouldEmitStopSignal && virtualDisplay != null) {
                     virtualDisplay.release();
@@ -792,6 +1196,42 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         });
     }
 
+    /**
+     * Called from native code with a JSON object describing a change of the native state, e.g.
+     * {@code {"type":"stateChanged","state":"casting","receiverName":"Living room"}}.
+     */
+    private void onNativeEvent(String json) {
+        try {
+            JSONObject event = new JSONObject(json);
+            String type = event.getString("type");
+            if ("gstLog".equals(type)) {
+                JSONArray lines = event.getJSONArray("lines");
+                for (int i = 0; i < lines.length(); i++) {
+                    Log.d(GST_LOG_TAG, lines.getString(i));
+                }
+            } else if ("stateChanged".equals(type)) {
+                String state = event.getString("state");
+                // The foreground service is only needed while the screen is being captured
+                if (!state.equals("waitingForMedia") && !state.equals("casting")
+                        && Build.VERSION.SDK_INT >= Build.VERSION_CODES.Q) {
+                    stopService(new Intent(this, ScreenCaptureService.class));
+                }
+            } else if ("connectionStatus".equals(type)) {
+                Log.i(TAG, "Connection " + event.getString("stage") + ": "
+                        + event.getString("message"));
+            } else if ("connectionError".equals(type)) {
+                Log.w(TAG, "Connection failed: " + event.getString("message"));
+            }
+        } catch (JSONException e) {
+            Log.e(TAG, "Invalid native event: " + json, e);
+        }
+    }
+
+    // Called from native code
+    private void restartDiscovery() {
+        discoverer.restart();
+    }
+
     // Called from native code
     private void stopCapture() {
         cleanupCapture(true);
@@ -807,11 +1247,36 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         startActivityForResult(intent, QR_SCAN_REQUEST_CODE);
     }
 
+    // Called from native code
+    private void pickImage() {
+        Intent intent = new Intent(Intent.ACTION_OPEN_DOCUMENT);
+        intent.addCategory(Intent.CATEGORY_OPENABLE);
+        intent.setType("image/*");
+        startActivityForResult(intent, IMAGE_PICK_REQUEST_CODE);
+    }
+
+    private void castPickedImage(Uri uri) {
+        String mimeType = getContentResolver().getType(uri);
+        String extension = MimeTypeMap.getSingleton().getExtensionFromMimeType(mimeType);
+        File file = new File(getCacheDir(), "cast-image." + (extension != null ? extension : "jpg"));
+        try (InputStream in = getContentResolver().openInputStream(uri); OutputStream out = new FileOutputStream(file)) {
+            byte[] buf = new byte[64 * 1024];
+            int n;
+            while ((n = in.read(buf)) > 0) {
+                out.write(buf, 0, n);
+            }
+        } catch (IOException e) {
+            Log.e(TAG, "Failed to copy picked image: " + e);
+            return;
+        }
+        nativeCastImage(file.getAbsolutePath(), mimeType);
+    }
+
     private void initializeCapture(int resultCode, Intent data) {
         mediaProjection = mediaProjectionManager.getMediaProjection(resultCode, data);
         mediaProjection.registerCallback(projectionCallback, null);
-        glHandler.post(() -> setupGles(new Dimensions(userMaxWidth, userMaxHeight), null));
-        nativeCaptureStarted();
+        glHandler.post(() -> setupCapture(new Dimensions(userMaxWidth, userMaxHeight), null));
+        nativeCaptureStarted(startAudioCapture());
     }
 
     @Override
@@ -841,12 +1306,52 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
         } else if (requestCode == QR_SCAN_REQUEST_CODE && resultCode == RESULT_OK) {
             String result = data.getStringExtra("SCAN_RESULT");
             nativeQrScanResult(result);
+        } else if (requestCode == IMAGE_PICK_REQUEST_CODE && resultCode == RESULT_OK && data != null && data.getData() != null) {
+            castPickedImage(data.getData());
         }
     }
 
-    native void nativeProcessFrame(int width, int height, ByteBuffer bufferY, ByteBuffer bufferU, ByteBuffer bufferV);
-
-    native void nativeCaptureStarted();
+    /** Lets native code allocate buffers for frames of the new size before the first one arrives. */
+    native void nativeFrameSizeChanged(int width, int height);
+
+    /**
+     * Strides follow {@link android.media.Image.Plane#getRowStride()} and {@link android.media.Image.Plane#getPixelStride()}.
+     * {@code timestampNs} is the {@code CLOCK_MONOTONIC} capture time, or 0 if unknown.
+     */
+    native void nativeProcessFrame(int width, int height,
+                                   ByteBuffer bufferY, int rowStrideY,
+                                   ByteBuffer bufferU, int rowStrideU, int pixelStrideU,
+                                   ByteBuffer bufferV, int rowStrideV, int pixelStrideV,
+                                   long timestampNs);
+
+    /**
+     * For {@code YUV_420_888} images whose chroma planes are interleaved (pixel stride 2). NV12 and
+     * NV21 are told apart by the addresses of {@code bufferU} and {@code bufferV}.
+     */
+    native void nativeProcessSemiPlanarFrame(int width, int height,
+                                             ByteBuffer bufferY, int rowStrideY,
+                                             ByteBuffer bufferU, ByteBuffer bufferV, int rowStrideUV,
+                                             long timestampNs);
+
+    /**
+     * For 10-bit {@code YCBCR_P010} images, whose U and V planes are views of the same interleaved
+     * memory. {@code hdr} selects the PQ transfer function.
+     */
+    native void nativeProcessP010Frame(int width, int height, boolean hdr,
+                                       ByteBuffer bufferY, int rowStrideY,
+                                       ByteBuffer bufferU, ByteBuffer bufferV, int rowStrideUV,
+                                       long timestampNs);
+
+    /**
+     * Interleaved 16-bit PCM from {@code AudioPlaybackCapture}. Only used when the capture was
+     * started with audio, {@code timestampNs} is in the {@code CLOCK_MONOTONIC} time base.
+     */
+    native void nativeProcessAudioFrame(ByteBuffer buffer, int size, int sampleRate, int channels,
+                                        long timestampNs);
+
+    native void nativeCaptureStarted(boolean withAudio);
+
+    native void nativeNetworkChanged(int networkType, boolean hasInternet);
 
     native void nativeCaptureStopped();
 
@@ -854,6 +1359,32 @@ public class MainActivity extends NativeActivity implements DisplayManager.Displ
 
     native void nativeQrScanResult(String result);
 
+    /** {@code mimeType} is the type reported by the content resolver, {@code null} if unknown. */
+    native void nativeCastImage(String path, String mimeType);
+
+    native void nativeSetHeadless(boolean headless);
+
+    /** {@code deviceId} is the service instance name the receiver was discovered under. */
+    native void nativeConnectReceiver(String deviceId);
+
+    /**
+     * {@code options} is a JSON object with any of {@code scaleWidth}, {@code scaleHeight},
+     * {@code maxFramerate}, {@code record}, {@code debugOverlay}, {@code latencyPreset}
+     * ({@code "UltraLow"}, {@code "Balanced"} or {@code "Quality"}), {@code ice}
+     * ({@code noHostOnCellular}, {@code noMdns} and {@code turnServer}) and
+     * {@code maxDurationSecs} (0 casts until stopped). Missing options use the defaults.
+     */
+    native void nativeStartCast(String options);
+
+    native void nativeStopCast();
+
+    /**
+     * {@code threshold} uses the {@code GST_DEBUG} syntax, e.g. {@code "webrtc*:6,3"}, and is
+     * ignored when empty. Recent log lines are delivered to {@link #onNativeEvent} while
+     * {@code streamLogs} is set.
+     */
+    native void nativeSetDebug(String threshold, boolean streamLogs);
+
     public class ProjectionCallback extends MediaProjection.Callback {
         @Override
         public void onStop() {
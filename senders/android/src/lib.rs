@@ -1,25 +1,211 @@
 use anyhow::{bail, Result};
-use fcast_sender_sdk::{context::CastContext, device, device::DeviceInfo};
+use fcast_sender_sdk::{
+    context::CastContext,
+    device,
+    device::{DeviceFeature, DeviceInfo},
+};
 use gst::prelude::{BufferPoolExt, BufferPoolExtManual};
 use gst_video::{VideoColorimetry, VideoFrameExt};
 use jni::{
     objects::{JByteBuffer, JObject, JString},
     JavaVM,
 };
-use mcore::{transmission::WhepSink, DeviceEvent, Event, ShouldQuit, SourceConfig};
-use parking_lot::{Condvar, Mutex};
-use std::{collections::HashMap, net::Ipv6Addr, sync::Arc};
-use tracing::{debug, error};
+use mcore::{
+    event_bus::{EventReceiver, EventSender},
+    hls_sink::HlsSink,
+    hostname::HostnameAdvertiser,
+    image_server::ImageServer,
+    transmission::{
+        CaptureConfig, CaptureStats, CastOptions, FrameDropPolicy, IceSettings, LatencyPreset,
+        WhepSink,
+    },
+    whep_signaller::CandidateFilter,
+    DeviceEvent, Event, ShouldQuit, SinkId, SourceConfig,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::Ipv6Addr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tracing::{debug, error, warn};
 
 lazy_static::lazy_static! {
-    pub static ref GLOB_EVENT_CHAN: (crossbeam_channel::Sender<Event>, crossbeam_channel::Receiver<Event>)
-        = crossbeam_channel::bounded(2);
-    pub static ref FRAME_PAIR: (Mutex<Option<gst_video::VideoFrame<gst_video::video_frame::Writable>>>, Condvar) = (Mutex::new(None), Condvar::new());
-    pub static ref FRAME_POOL: Mutex<gst_video::VideoBufferPool> = Mutex::new(gst_video::VideoBufferPool::new());
+    /// Sender of the running event loop, used by the JNI entry points
+    pub static ref GLOB_EVENT_TX: Mutex<Option<EventSender>> = Mutex::new(None);
+    pub static ref FRAME_QUEUE: (crossbeam_channel::Sender<gst_video::VideoFrame<gst_video::video_frame::Writable>>, crossbeam_channel::Receiver<gst_video::VideoFrame<gst_video::video_frame::Writable>>)
+        = crossbeam_channel::bounded(MAX_FRAME_QUEUE_CAPACITY);
+    pub static ref FRAME_POOLS: Mutex<FramePools> = Mutex::new(FramePools::default());
+    pub static ref CAPTURE_STATS: Arc<CaptureStats> = Arc::new(CaptureStats::default());
+    pub static ref CAPTURE_CONFIG: Mutex<CaptureConfig> = Mutex::new(CaptureConfig::default());
+    /// When the last frame was accepted into the frame queue
+    static ref LAST_FRAME_INSTANT: Mutex<Option<std::time::Instant>> = Mutex::new(None);
+    /// Audio source of the active capture session, if it captures device audio
+    static ref AUDIO_SRC: Mutex<Option<gst_app::AppSrc>> = Mutex::new(None);
+    /// GStreamer log lines waiting to be sent to Java while log streaming is enabled
+    static ref GST_LOG_LINES: Mutex<std::collections::VecDeque<String>> = Mutex::new(Default::default());
 }
 
 slint::include_modules!();
 
+/// Stable identity of a discovered receiver: its full mDNS service instance name, e.g.
+/// `Living room._fcast._tcp.local`. Unlike the display name it is unique on the network because
+/// mDNS resolves conflicts by renaming the instance.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DeviceId(String);
+
+impl DeviceId {
+    fn from_service_instance(instance_name: &str) -> Self {
+        Self(instance_name.to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reachability {
+    Unknown,
+    Reachable(std::time::Duration),
+    Unreachable,
+}
+
+#[derive(Debug)]
+struct KnownDevice {
+    info: DeviceInfo,
+    /// `false` once discovery reported the receiver as lost, it is kept around so it can still be
+    /// picked if it comes back under the same address
+    online: bool,
+    last_seen: std::time::Instant,
+    /// When discovery last reported the receiver as lost while it is still shown as online, see
+    /// [`REMOVAL_DEBOUNCE`]
+    lost_at: Option<std::time::Instant>,
+    reachability: Reachability,
+}
+
+/// Replace the addresses of each family (IPv4 or IPv6) that `update` has, keeping those of the
+/// other family. The same instance is usually resolved once per address family, so a resolve only
+/// tells which addresses of its own family the receiver has.
+fn merge_addresses(
+    known: &mut Vec<fcast_sender_sdk::IpAddr>,
+    update: Vec<fcast_sender_sdk::IpAddr>,
+) {
+    let is_v4 =
+        |addr: &fcast_sender_sdk::IpAddr| matches!(addr, fcast_sender_sdk::IpAddr::V4 { .. });
+    let has_v4 = update.iter().any(is_v4);
+    let has_v6 = update.iter().any(|addr| !is_v4(addr));
+    known.retain(|addr| if is_v4(addr) { !has_v4 } else { !has_v6 });
+    for addr in update {
+        if !known.contains(&addr) {
+            known.push(addr);
+        }
+    }
+}
+
+fn format_last_seen(elapsed: std::time::Duration) -> String {
+    match elapsed.as_secs() {
+        0..60 => "just now".to_owned(),
+        secs @ 60..3600 => format!("{} min ago", secs / 60),
+        secs => format!("{} h ago", secs / 3600),
+    }
+}
+
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// How long the receiver gets to start playing the hostname based play URL before it is sent the
+/// IP based one instead. Many receivers, e.g. Android TV before 12, can't resolve `.local` names.
+const HOSTNAME_PLAY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(8);
+/// How long to wait for the receiver to confirm it stopped playback before disconnecting anyway
+const STOP_PLAYBACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+/// How long a receiver has to stay lost before it is shown as offline. Discovery frequently
+/// reports receivers as lost right before finding them again, e.g. when one of its address records
+/// expires.
+const REMOVAL_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(5);
+/// How often receivers are probed to find the ones that vanished without discovery noticing
+const DEVICE_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Online receivers that have not been seen or reached for this long are shown as offline
+const DEVICE_EXPIRY: std::time::Duration = std::time::Duration::from_secs(3 * 60);
+/// Offline receivers that have not come back for this long are removed from the list
+const OFFLINE_DEVICE_EXPIRY: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Number of failed connection attempts before giving up on a receiver we have not yet connected to
+const MAX_CONNECT_ATTEMPTS: u32 = 5;
+/// Upper bound for [`CaptureConfig::max_queued_frames`]
+const MAX_FRAME_QUEUE_CAPACITY: usize = 8;
+/// Rotating the device flips between two resolutions, so keep a pool for each
+const MAX_FRAME_POOLS: usize = 2;
+const SESSION_FILE_NAME: &str = "session.json";
+/// Bitrates casts to each receiver reached, used to recommend cast settings
+const BANDWIDTH_HISTORY_FILE_NAME: &str = "bandwidth.json";
+/// Segments of casts to receivers without WHEP support are written here
+const HLS_DIR_NAME: &str = "hls";
+/// Rate of the black frames that keep a prepared pipeline running until capture starts
+const WARM_UP_FRAME_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// Set once capture delivers frames, until then the cast pipeline is fed black frames
+static CAPTURE_LIVE: AtomicBool = AtomicBool::new(false);
+/// Oldest lines are dropped when more are logged between two flushes
+const GST_LOG_LINES_CAPACITY: usize = 500;
+const GST_LOG_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Run the event loop without creating `MainWindow`, set by Java before the activity starts
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+/// How much captured audio can wait in the audio source before the oldest is dropped
+const AUDIO_SRC_MAX_QUEUED_MS: u64 = 200;
+
+/// Buffer pools for the most recently used capture formats, most recent last.
+#[derive(Default)]
+pub struct FramePools {
+    pools: Vec<(gst_video::VideoInfo, gst_video::VideoBufferPool)>,
+}
+
+impl FramePools {
+    fn get(&mut self, info: &gst_video::VideoInfo) -> Result<&gst_video::VideoBufferPool> {
+        if let Some(idx) = self
+            .pools
+            .iter()
+            .position(|(pool_info, _)| pool_info == info)
+        {
+            let entry = self.pools.remove(idx);
+            self.pools.push(entry);
+        } else {
+            debug!(?info, "Creating frame pool");
+
+            let pool = gst_video::VideoBufferPool::new();
+            let mut config = pool.config();
+            // Every buffer that can be in flight is allocated when the pool is activated so the
+            // first frames after a resolution change don't allocate
+            config.set_params(
+                Some(&info.to_caps()?),
+                info.size() as u32,
+                CaptureConfig::default().max_queued_frames as u32 + 2,
+                30,
+            );
+            pool.set_config(config)?;
+            pool.set_active(true)?;
+
+            if self.pools.len() >= MAX_FRAME_POOLS {
+                let (_, old_pool) = self.pools.remove(0);
+                let _ = old_pool.set_active(false);
+            }
+            self.pools.push((info.clone(), pool));
+        }
+
+        Ok(&self.pools.last().unwrap().1)
+    }
+
+    /// Allocate the pool for a new capture size before its first frame arrives. Frames are
+    /// assumed to keep the format and colorimetry of the most recent ones.
+    fn prepare(&mut self, width: u32, height: u32) -> Result<()> {
+        let (format, colorimetry) = match self.pools.last() {
+            Some((info, _)) => (info.format(), info.colorimetry()),
+            None => (gst_video::VideoFormat::I420, sdr_colorimetry()),
+        };
+        let info = gst_video::VideoInfo::builder(format, width, height)
+            .colorimetry(&colorimetry)
+            .build()?;
+        self.get(&info)?;
+        Ok(())
+    }
+}
+
 macro_rules! log_err {
     ($res:expr, $msg: expr) => {
         if let Err(err) = ($res) {
@@ -32,9 +218,108 @@ macro_rules! log_err {
 enum JavaMethod {
     StopCapture,
     ScanQr,
+    PickImage,
+    RestartDiscovery,
 }
 
-fn call_java_method_no_args(app: &slint::android::AndroidApp, method: JavaMethod) {
+/// Native state pushed to `MainActivity.onNativeEvent` as JSON, so the Android side can react to
+/// it without polling, e.g. by stopping the screen capture service.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum JavaEvent {
+    #[serde(rename_all = "camelCase")]
+    StateChanged {
+        state: &'static str,
+        receiver_name: Option<String>,
+    },
+    /// Reply to [`Event::SetGstDebug`] with the resulting threshold of every category
+    GstDebugCategories {
+        categories: Vec<GstDebugCategory>,
+    },
+    GstLog {
+        lines: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ConnectionStatus {
+        stage: &'static str,
+        receiver_name: String,
+        message: String,
+    },
+    ConnectionError {
+        message: String,
+    },
+}
+
+/// Progress of connecting to a receiver, until it reports itself as connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionStage {
+    /// Finding out which of the receiver's addresses answers
+    Resolving,
+    /// The receiver's device is trying to open a connection
+    Connecting,
+    /// An address answered, waiting for the receiver to accept the session
+    Handshaking,
+}
+
+fn connection_stage_name(stage: ConnectionStage) -> &'static str {
+    match stage {
+        ConnectionStage::Resolving => "resolving",
+        ConnectionStage::Connecting => "connecting",
+        ConnectionStage::Handshaking => "handshaking",
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct GstDebugCategory {
+    name: String,
+    threshold: String,
+}
+
+fn app_state_name(state: AppState) -> &'static str {
+    match state {
+        AppState::Disconnected => "disconnected",
+        AppState::Connecting => "connecting",
+        AppState::SelectingSettings => "selectingSettings",
+        AppState::WaitingForMedia => "waitingForMedia",
+        AppState::Casting => "casting",
+    }
+}
+
+fn send_java_event(app: &slint::android::AndroidApp, event: &JavaEvent) {
+    let json = match serde_json::to_string(event) {
+        Ok(json) => json,
+        Err(err) => {
+            error!(?err, ?event, "Failed to serialize java event");
+            return;
+        }
+    };
+
+    let (vm, activity) = java_vm_and_activity(app);
+    let res = vm.get_env().and_then(|mut env| {
+        let json = env.new_string(json)?;
+        env.call_method(
+            activity,
+            "onNativeEvent",
+            "(Ljava/lang/String;)V",
+            &[(&json).into()],
+        )?;
+        Ok(())
+    });
+    if let Err(err) = res {
+        error!(?err, ?event, "Failed to send java event");
+    }
+}
+
+fn send_global_event(event: Event) -> Result<()> {
+    match GLOB_EVENT_TX.lock().as_ref() {
+        Some(event_tx) => event_tx
+            .send(event)
+            .map_err(|err| anyhow::anyhow!("Failed to send event: {err}")),
+        None => bail!("Event loop is not running"),
+    }
+}
+
+fn java_vm_and_activity(app: &slint::android::AndroidApp) -> (JavaVM, JObject<'static>) {
     let vm = unsafe {
         let ptr = app.vm_as_ptr() as *mut jni::sys::JavaVM;
         assert!(!ptr.is_null(), "JavaVM ptr is null");
@@ -45,10 +330,17 @@ fn call_java_method_no_args(app: &slint::android::AndroidApp, method: JavaMethod
         assert!(!ptr.is_null(), "Activity ptr is null");
         JObject::from_raw(ptr)
     };
+    (vm, activity)
+}
+
+fn call_java_method_no_args(app: &slint::android::AndroidApp, method: JavaMethod) {
+    let (vm, activity) = java_vm_and_activity(app);
 
     let method_name = match method {
         JavaMethod::StopCapture => "stopCapture",
         JavaMethod::ScanQr => "scanQr",
+        JavaMethod::PickImage => "pickImage",
+        JavaMethod::RestartDiscovery => "restartDiscovery",
     };
 
     match vm.get_env() {
@@ -60,200 +352,1327 @@ fn call_java_method_no_args(app: &slint::android::AndroidApp, method: JavaMethod
     }
 }
 
+/// Tracks the state shown by the UI and rejects transitions that would leave the UI and the
+/// backend out of sync, e.g. jumping back to settings while a cast is running.
+#[derive(Debug)]
+struct AppStateMachine {
+    state: AppState,
+}
+
+impl AppStateMachine {
+    fn new() -> Self {
+        Self {
+            state: AppState::Disconnected,
+        }
+    }
+
+    fn is_valid_transition(from: AppState, to: AppState) -> bool {
+        use AppState::*;
+        matches!(
+            (from, to),
+            (_, Disconnected)
+                | (Disconnected | Connecting, Connecting)
+                // Back from a screen cast that was replaced by other media, see
+                // `Application::replace_screen_cast`
+                | (Connecting | WaitingForMedia | Casting, SelectingSettings)
+                | (SelectingSettings, WaitingForMedia)
+                | (WaitingForMedia, Casting)
+        )
+    }
+
+    /// Returns `true` if the state was changed
+    fn transition(&mut self, to: AppState) -> bool {
+        let from = self.state;
+        if !Self::is_valid_transition(from, to) {
+            warn!(?from, ?to, "Rejected invalid state transition");
+            return false;
+        }
+
+        debug!(?from, ?to, "State transition");
+        self.state = to;
+        true
+    }
+}
+
+/// Settings picked for a cast, kept so an interrupted cast can be started again
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CastSettings {
+    scale_width: u32,
+    scale_height: u32,
+    max_framerate: u32,
+    /// A resumed cast is recorded into a new file
+    #[serde(default)]
+    record: bool,
+    debug_overlay: bool,
+    #[serde(with = "LatencyPresetDef")]
+    latency_preset: LatencyPreset,
+    #[serde(default, with = "IceSettingsDef")]
+    ice: IceSettings,
+    #[serde(default)]
+    max_duration: Option<std::time::Duration>,
+}
+
+/// Saved form of [`LatencyPreset`], mcore has no serde dependency on Android
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(remote = "LatencyPreset")]
+enum LatencyPresetDef {
+    UltraLow,
+    Balanced,
+    Quality,
+}
+
+/// Saved form of [`IceSettings`]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(remote = "IceSettings", rename_all = "camelCase")]
+struct IceSettingsDef {
+    no_host_on_cellular: bool,
+    no_mdns: bool,
+    turn_server: Option<String>,
+}
+
+/// Options of `MainActivity.nativeStartCast`, passed as JSON so callers only set what they need
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct StartCastOptions {
+    scale_width: u32,
+    scale_height: u32,
+    max_framerate: u32,
+    record: bool,
+    debug_overlay: bool,
+    #[serde(with = "LatencyPresetDef")]
+    latency_preset: LatencyPreset,
+    #[serde(with = "IceSettingsDef")]
+    ice: IceSettings,
+    /// The cast runs until stopped if this is 0
+    max_duration_secs: u64,
+}
+
+impl Default for StartCastOptions {
+    fn default() -> Self {
+        Self {
+            scale_width: 1920,
+            scale_height: 1080,
+            max_framerate: 30,
+            record: false,
+            debug_overlay: false,
+            latency_preset: LatencyPreset::default(),
+            ice: IceSettings::default(),
+            max_duration_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedAddress {
+    ip: std::net::IpAddr,
+    scope_id: u32,
+}
+
+/// Written to the internal data directory while casting and removed when the cast is stopped, so
+/// it is only left behind when the process was killed mid-cast.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedSession {
+    device_name: String,
+    addresses: Vec<SavedAddress>,
+    port: u16,
+    settings: CastSettings,
+}
+
+impl SavedSession {
+    fn new(device_info: &DeviceInfo, settings: CastSettings) -> Self {
+        Self {
+            device_name: device_info.name.clone(),
+            addresses: device_info
+                .addresses
+                .iter()
+                .map(|addr| SavedAddress {
+                    ip: addr.into(),
+                    scope_id: match addr {
+                        fcast_sender_sdk::IpAddr::V6 { scope_id, .. } => *scope_id,
+                        fcast_sender_sdk::IpAddr::V4 { .. } => 0,
+                    },
+                })
+                .collect(),
+            port: device_info.port,
+            settings,
+        }
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|saved| {
+                let mut addr = fcast_sender_sdk::IpAddr::from(saved.ip);
+                if let fcast_sender_sdk::IpAddr::V6 { scope_id, .. } = &mut addr {
+                    *scope_id = saved.scope_id;
+                }
+                addr
+            })
+            .collect();
+        DeviceInfo::fcast(self.device_name.clone(), addresses, self.port)
+    }
+}
+
+/// Streams the screen to the receiver, over HLS if it can't play WHEP streams
+#[derive(Debug)]
+enum CastSink {
+    Whep(WhepSink),
+    Hls(HlsSink),
+}
+
+impl CastSink {
+    fn id(&self) -> SinkId {
+        match self {
+            CastSink::Whep(sink) => sink.id(),
+            CastSink::Hls(sink) => sink.id(),
+        }
+    }
+
+    fn get_play_msg(&self, addr: std::net::IpAddr, port: u16) -> (String, String) {
+        match self {
+            CastSink::Whep(sink) => sink.get_play_msg(addr, port),
+            CastSink::Hls(sink) => sink.get_play_msg(addr, port),
+        }
+    }
+
+    fn get_play_msg_for_host(&self, host: &str, port: u16) -> (String, String) {
+        match self {
+            CastSink::Whep(sink) => sink.get_play_msg_for_host(host, port),
+            CastSink::Hls(sink) => sink.get_play_msg_for_host(host, port),
+        }
+    }
+
+    fn pause(&self) {
+        match self {
+            CastSink::Whep(sink) => sink.pause(),
+            CastSink::Hls(sink) => sink.pause(),
+        }
+    }
+
+    fn resume(&self) {
+        match self {
+            CastSink::Whep(sink) => sink.resume(),
+            CastSink::Hls(sink) => sink.resume(),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        match self {
+            CastSink::Whep(sink) => sink.shutdown(),
+            CastSink::Hls(sink) => sink.shutdown(),
+        }
+    }
+}
+
+/// A cast pipeline that is running ahead of capture, see [`Application::prepare_cast`]
+struct PreparedCast {
+    sink: CastSink,
+    appsrc: gst_app::AppSrc,
+}
+
+/// The IP based play URL, sent if the receiver doesn't start playing the hostname based one
+#[derive(Debug)]
+struct PlayUrlFallback {
+    content_type: String,
+    url: String,
+    /// The URL that was sent first
+    hostname_url: String,
+    deadline: tokio::time::Instant,
+}
+
+/// A stop request waiting for the receiver to report that it stopped playing before disconnecting
+#[derive(Debug)]
+struct PendingStop {
+    device_id: usize,
+    stopped_tx: tokio::sync::oneshot::Sender<()>,
+}
+
 struct Application {
     ui_weak: slint::Weak<MainWindow>,
-    event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
-    devices: HashMap<String, DeviceInfo>,
+    state: AppStateMachine,
+    event_tx: EventSender,
+    devices: HashMap<DeviceId, KnownDevice>,
     cast_ctx: CastContext,
     active_device: Option<Arc<dyn device::CastingDevice>>,
+    /// Discovered receiver `active_device` was created from, `None` for receivers connected to
+    /// through a QR code or a resumed session
+    active_device_id: Option<DeviceId>,
     current_device_id: usize,
     local_address: Option<fcast_sender_sdk::IpAddr>,
     android_app: slint::android::AndroidApp,
-    tx_sink: Option<WhepSink>,
+    tx_sink: Option<CastSink>,
+    /// Cast over HLS because the receiver can't play WHEP streams
+    hls_fallback: bool,
     our_source_url: Option<String>,
+    play_url_fallback: Option<PlayUrlFallback>,
+    /// Hostname based play URL that was replaced by the IP based one, the receiver can keep
+    /// reporting it as its source until it loaded the new URL
+    superseded_source_url: Option<String>,
+    record_path: Option<std::path::PathBuf>,
+    debug_overlay: bool,
+    latency_preset: LatencyPreset,
+    ice_settings: IceSettings,
+    capture_framerate: u32,
+    receiver_playback_state: device::PlaybackState,
+    image_server: Option<ImageServer>,
+    connect_attempts: u32,
+    connection_stage: Option<ConnectionStage>,
+    mdns_name: String,
+    hostname_advertiser: Option<HostnameAdvertiser>,
+    pending_stop: Option<PendingStop>,
+    prepared_cast: Option<PreparedCast>,
+    /// Ports of the prepared pipeline's WHEP server, the play message is sent once casting starts
+    signaller_ports: Option<(u16, u16)>,
+    network: Option<(mcore::NetworkType, bool)>,
+    cast_settings: Option<CastSettings>,
+    /// A session left behind by a killed process, offered in the UI until another cast starts
+    saved_session: Option<SavedSession>,
+    /// Settings to start casting with as soon as the resumed session's receiver is connected
+    pending_resume: Option<CastSettings>,
+    /// Receiver name to the encoder bitrate the last cast to it reached
+    bandwidth_history: HashMap<String, u64>,
+    /// Set while GStreamer logs are streamed to Java
+    gst_log_function: Option<gst::log::DebugLogFunction>,
+    max_cast_duration: Option<std::time::Duration>,
+    /// When the running screen cast hits its time limit
+    cast_deadline: Option<tokio::time::Instant>,
 }
 
 impl Application {
     pub async fn new(
         ui_weak: slint::Weak<MainWindow>,
-        event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
+        event_tx: EventSender,
         android_app: slint::android::AndroidApp,
     ) -> Result<Self> {
-        std::thread::spawn({
-            let event_tx = event_tx.clone();
-            move || loop {
-                match GLOB_EVENT_CHAN.1.recv() {
-                    Ok(event) => {
-                        if let Err(err) = event_tx.send(event) {
-                            error!("Failed to forward event to event loop: {err}");
-                            break;
-                        }
-                    }
-                    Err(err) => {
-                        error!("Failed to receive event from the global event channel: {err}");
-                        break;
-                    }
-                }
-            }
-        });
-
         Ok(Self {
             ui_weak,
+            state: AppStateMachine::new(),
             event_tx,
             devices: HashMap::new(),
             cast_ctx: CastContext::new()?,
             active_device: None,
+            active_device_id: None,
             current_device_id: 0,
             local_address: None,
             android_app,
             tx_sink: None,
+            hls_fallback: false,
             our_source_url: None,
+            play_url_fallback: None,
+            superseded_source_url: None,
+            record_path: None,
+            debug_overlay: false,
+            latency_preset: LatencyPreset::default(),
+            ice_settings: IceSettings::default(),
+            capture_framerate: 30,
+            receiver_playback_state: device::PlaybackState::Idle,
+            image_server: None,
+            connect_attempts: 0,
+            connection_stage: None,
+            mdns_name: format!(
+                "fcast-sender-{}",
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            ),
+            hostname_advertiser: None,
+            pending_stop: None,
+            prepared_cast: None,
+            signaller_ports: None,
+            network: None,
+            cast_settings: None,
+            saved_session: None,
+            pending_resume: None,
+            bandwidth_history: HashMap::new(),
+            gst_log_function: None,
+            max_cast_duration: None,
+            cast_deadline: None,
         })
     }
 
+    fn change_state(&mut self, to: AppState) -> Result<()> {
+        if self.state.transition(to) {
+            let java_event = JavaEvent::StateChanged {
+                state: app_state_name(to),
+                receiver_name: self.active_device.as_ref().map(|device| device.name()),
+            };
+            self.ui_weak.upgrade_in_event_loop(move |ui| {
+                ui.global::<Bridge>().invoke_change_state(to);
+            })?;
+            self.post_java_event(java_event)?;
+        }
+
+        Ok(())
+    }
+
+    fn post_java_event(&self, event: JavaEvent) -> Result<()> {
+        let android_app = self.android_app.clone();
+        // Not sent through the window so it also reaches Java when running headless
+        slint::invoke_from_event_loop(move || send_java_event(&android_app, &event))?;
+
+        Ok(())
+    }
+
+    fn set_gst_log_streaming(&mut self, enabled: bool) {
+        match (enabled, self.gst_log_function.take()) {
+            (true, None) => {
+                debug!("Streaming GStreamer logs");
+                let function = gst::log::add_log_function(
+                    |category, level, file, function, line, _object, message| {
+                        let Some(message) = message.get() else {
+                            return;
+                        };
+                        let mut lines = GST_LOG_LINES.lock();
+                        if lines.len() >= GST_LOG_LINES_CAPACITY {
+                            lines.pop_front();
+                        }
+                        lines.push_back(format!(
+                            "{level:?} {} {file}:{line}:{function}: {message}",
+                            category.name()
+                        ));
+                    },
+                );
+                self.gst_log_function = Some(function);
+            }
+            (true, Some(function)) => self.gst_log_function = Some(function),
+            (false, Some(function)) => {
+                debug!("Stopped streaming GStreamer logs");
+                gst::log::remove_log_function(function);
+                GST_LOG_LINES.lock().clear();
+            }
+            (false, None) => (),
+        }
+    }
+
+    async fn stop_cast_at_time_limit(&mut self) -> Result<()> {
+        // Cleared up front so the deadline does not fire again if stopping fails
+        self.cast_deadline = None;
+        let minutes = self.max_cast_duration.unwrap_or_default().as_secs() / 60;
+        debug!(minutes, "Cast reached its time limit");
+        self.change_state(AppState::Disconnected)?;
+        self.stop_cast(true).await?;
+        self.set_connection_error(format!("Casting stopped after the {minutes} minute limit"))?;
+
+        Ok(())
+    }
+
+    fn flush_gst_log(&self) -> Result<()> {
+        if self.gst_log_function.is_none() {
+            return Ok(());
+        }
+        let lines = GST_LOG_LINES.lock().drain(..).collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        self.post_java_event(JavaEvent::GstLog { lines })
+    }
+
     fn update_receivers_in_ui(&mut self) -> Result<()> {
-        let receivers = self
+        let mut receivers = self
             .devices
             .iter()
-            .filter(|(_, info)| !info.addresses.is_empty() && info.port != 0)
-            .map(|(name, _)| slint::SharedString::from(name))
-            .collect::<Vec<slint::SharedString>>();
+            .filter(|(_, device)| !device.info.addresses.is_empty() && device.info.port != 0)
+            .collect::<Vec<(&DeviceId, &KnownDevice)>>();
+        // Online and reachable receivers first, then by name with the id as a tie breaker for
+        // duplicate names
+        let rank = |device: &KnownDevice| match (device.online, device.reachability) {
+            (true, Reachability::Reachable(_)) => 0,
+            (true, Reachability::Unknown) => 1,
+            (true, Reachability::Unreachable) => 2,
+            (false, _) => 3,
+        };
+        receivers.sort_by(|(a_id, a), (b_id, b)| {
+            rank(a)
+                .cmp(&rank(b))
+                .then_with(|| a.info.name.cmp(&b.info.name))
+                .then_with(|| a_id.0.cmp(&b_id.0))
+        });
+        let receivers = receivers
+            .into_iter()
+            .map(|(id, device)| DeviceEntry {
+                id: id.0.as_str().into(),
+                name: device.info.name.as_str().into(),
+                address: device
+                    .info
+                    .addresses
+                    .first()
+                    .map(|addr| std::net::IpAddr::from(addr).to_string())
+                    .unwrap_or_default()
+                    .into(),
+                port: device.info.port as i32,
+                protocol: format!("{:?}", device.info.protocol).into(),
+                online: device.online,
+                last_seen: format_last_seen(device.last_seen.elapsed()).into(),
+                reachability: match device.reachability {
+                    Reachability::Unknown => "".into(),
+                    Reachability::Reachable(rtt) => format!("{} ms", rtt.as_millis()).into(),
+                    Reachability::Unreachable => "unreachable".into(),
+                },
+            })
+            .collect::<Vec<DeviceEntry>>();
         self.ui_weak.upgrade_in_event_loop(move |ui| {
-            let model = std::rc::Rc::new(slint::VecModel::<slint::SharedString>::from_iter(
-                receivers.into_iter(),
-            ));
+            let model = std::rc::Rc::new(slint::VecModel::<DeviceEntry>::from(receivers));
             ui.global::<Bridge>().set_devices(model.into());
         })?;
 
         Ok(())
     }
 
-    fn add_or_update_device(&mut self, device_info: DeviceInfo) -> Result<()> {
-        self.devices.insert(device_info.name.clone(), device_info);
+    fn add_or_update_device(&mut self, id: DeviceId, device_info: DeviceInfo) -> Result<()> {
+        let now = std::time::Instant::now();
+        match self.devices.get_mut(&id) {
+            Some(device) if device.info.port == device_info.port => {
+                merge_addresses(&mut device.info.addresses, device_info.addresses);
+                device.info.protocol = device_info.protocol;
+                device.online = true;
+                device.last_seen = now;
+                device.lost_at = None;
+            }
+            _ => {
+                self.devices.insert(
+                    id.clone(),
+                    KnownDevice {
+                        info: device_info,
+                        online: true,
+                        last_seen: now,
+                        lost_at: None,
+                        reachability: Reachability::Unknown,
+                    },
+                );
+            }
+        }
+        let device = &self.devices[&id];
+        // Let the reconnect logic of the active session pick up addresses from a new network
+        if let Some(active_device) = self.active_device.as_ref() {
+            if self.active_device_id.as_ref() == Some(&id)
+                && active_device.get_addresses() != device.info.addresses
+            {
+                debug!(addresses = ?device.info.addresses, "Updating addresses of active device");
+                active_device.set_addresses(device.info.addresses.clone());
+            }
+        }
+        self.probe_device(&id, &device.info);
         self.update_receivers_in_ui()?;
         Ok(())
     }
 
-    async fn stop_cast(&mut self, stop_playback: bool) -> Result<()> {
-        let android_app = self.android_app.clone();
-        self.ui_weak.upgrade_in_event_loop(move |_| {
-            call_java_method_no_args(&android_app, JavaMethod::StopCapture);
-        })?;
+    /// Check in the background which of the device's addresses can be reached from the current
+    /// network. The result is delivered as [`Event::DeviceProbed`].
+    fn probe_device(&self, id: &DeviceId, device_info: &DeviceInfo) {
+        let event_tx = self.event_tx.clone();
+        let instance = id.0.clone();
+        let addrs = device_info.addresses.clone();
+        let port = device_info.port;
+        tokio::spawn(async move {
+            let result = mcore::reachability::probe(&addrs, port, PROBE_TIMEOUT).await;
+            debug!(instance, ?result, "Probed device");
+            log_err!(
+                event_tx.send(Event::DeviceProbed { instance, result }),
+                "Failed to send device probed event"
+            );
+        });
+    }
 
-        if let Some(active_device) = self.active_device.take() {
-            tokio::spawn(async move {
-                if stop_playback {
-                    debug!("Stopping playback");
-                    log_err!(active_device.stop_playback(), "Failed to stop playback");
-                    // NOTE: Instead of waiting for the PlaybackState::Idle event in the main loop we just sleep here
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                }
-                debug!("Disconnecting from active device");
-                log_err!(
-                    active_device.disconnect(),
-                    "Failed to disconnect from active device"
-                );
-            });
+    /// Build the cast pipeline. It is fed black frames until capture starts, see [`CAPTURE_LIVE`].
+    /// Only the capture stats and preview of `options` apply to HLS pipelines.
+    fn create_cast_pipeline(
+        &self,
+        with_audio: bool,
+        hls: bool,
+        mut options: CastOptions,
+    ) -> Result<PreparedCast> {
+        let appsrc = gst_app::AppSrc::builder()
+            .caps(
+                &gst_video::VideoCapsBuilder::new()
+                    .format_list([
+                        gst_video::VideoFormat::I420,
+                        gst_video::VideoFormat::Nv12,
+                        gst_video::VideoFormat::Nv21,
+                        gst_video::VideoFormat::P01010le,
+                    ])
+                    // .framerate(gst::Fraction::new(0, 1))
+                    .build(),
+            )
+            .is_live(true)
+            .format(gst::Format::Time)
+            .max_buffers(1)
+            .build();
+        self.set_capture_latency(&appsrc);
+
+        let warm_up_frame = warm_up_frame()?;
+        let mut caps = None::<gst::Caps>;
+        appsrc.set_callbacks(
+            gst_app::AppSrcCallbacks::builder()
+                .need_data(move |appsrc, _| {
+                    use gst_video::prelude::*;
+
+                    let frame = loop {
+                        match FRAME_QUEUE.1.recv_timeout(WARM_UP_FRAME_INTERVAL) {
+                            Ok(frame) => break frame,
+                            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                                if !CAPTURE_LIVE.load(Ordering::Relaxed) {
+                                    set_caps_if_changed(appsrc, &mut caps, &warm_up_frame.0);
+                                    let mut buffer = warm_up_frame.1.clone();
+                                    buffer
+                                        .make_mut()
+                                        .set_pts(capture_running_time(appsrc, None));
+                                    let _ = appsrc.push_buffer(buffer);
+                                    return;
+                                }
+                            }
+                            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                                error!("Frame queue is disconnected");
+                                return;
+                            }
+                        }
+                    };
+
+                    // Built from the frame's info so the colorimetry of 10-bit HDR frames
+                    // reaches the encoder
+                    set_caps_if_changed(appsrc, &mut caps, &frame.info().to_caps().unwrap());
+
+                    let mut buffer = frame.into_buffer();
+                    let pts = capture_running_time(appsrc, buffer.pts());
+                    buffer.make_mut().set_pts(pts);
+
+                    let _ = appsrc.push_buffer(buffer);
+                })
+                .build(),
+        );
+
+        let video = mcore::VideoSource::Source(appsrc.clone());
+        let source_config = if with_audio {
+            // Caps are set by the first audio frame
+            let audio_src = gst_app::AppSrc::builder()
+                .is_live(true)
+                .format(gst::Format::Time)
+                .max_time(gst::ClockTime::from_mseconds(AUDIO_SRC_MAX_QUEUED_MS))
+                .leaky_type(gst_app::AppLeakyType::Downstream)
+                .build();
+            *AUDIO_SRC.lock() = Some(audio_src.clone());
+            SourceConfig::AudioVideo {
+                video,
+                audio: mcore::AudioSource::Source(audio_src),
+            }
+        } else {
+            SourceConfig::Video(video)
+        };
+
+        let preview_sink = gst_app::AppSink::builder().build();
+        preview_sink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample({
+                    let ui_weak = self.ui_weak.clone();
+                    move |appsink| on_preview_sample(appsink, &ui_weak)
+                })
+                .build(),
+        );
+        options.preview_sink = Some(preview_sink);
+
+        let sink = if hls {
+            if options.record_to.is_some() || options.debug_overlay {
+                warn!("Recording and the debug overlay are not available when casting over HLS");
+            }
+            let dir = self
+                .data_file_path(HLS_DIR_NAME)
+                .ok_or(anyhow::anyhow!("No directory to write HLS segments to"))?;
+            CastSink::Hls(HlsSink::new(
+                source_config,
+                self.event_tx.clone(),
+                tokio::runtime::Handle::current(),
+                dir,
+                options.preview_sink,
+            )?)
+        } else {
+            CastSink::Whep(WhepSink::new(
+                source_config,
+                self.event_tx.clone(),
+                tokio::runtime::Handle::current(),
+                1920,
+                1080,
+                30,
+                options,
+            )?)
+        };
+
+        Ok(PreparedCast { sink, appsrc })
+    }
+
+    fn set_capture_latency(&self, appsrc: &gst_app::AppSrc) {
+        // A frame can wait in the queue for up to its capacity worth of frame intervals before
+        // it is pushed
+        let max_queued_frames = CAPTURE_CONFIG.lock().max_queued_frames as u64;
+        let frame_interval = gst::ClockTime::SECOND / self.capture_framerate.max(1) as u64;
+        appsrc.set_latency(frame_interval, frame_interval * max_queued_frames);
+    }
+
+    /// Start the cast pipeline while the user is still picking settings, so the WHEP server and
+    /// webrtcsink's codec discovery are done by the time capture starts.
+    fn prepare_cast(&mut self) {
+        if self.prepared_cast.is_some() || self.tx_sink.is_some() {
+            return;
         }
 
-        if let Some(mut tx_sink) = self.tx_sink.take() {
-            tx_sink.shutdown();
+        CAPTURE_LIVE.store(false, Ordering::Relaxed);
+        let options = CastOptions {
+            capture_stats: Arc::clone(&CAPTURE_STATS),
+            ..Default::default()
+        };
+        match self.create_cast_pipeline(false, false, options) {
+            Ok(prepared) => {
+                debug!("Prepared cast pipeline");
+                self.prepared_cast = Some(prepared);
+            }
+            Err(err) => error!(?err, "Failed to prepare cast pipeline"),
+        }
+    }
+
+    fn discard_prepared_cast(&mut self) {
+        if let Some(mut prepared) = self.prepared_cast.take() {
+            debug!("Discarding prepared cast pipeline");
+            prepared.sink.shutdown();
+        }
+        self.signaller_ports = None;
+    }
+
+    fn data_file_path(&self, file_name: &str) -> Option<std::path::PathBuf> {
+        let path = self.android_app.internal_data_path();
+        if path.is_none() {
+            error!(file_name, "Internal data path is missing");
+        }
+        path.map(|dir| dir.join(file_name))
+    }
+
+    fn session_path(&self) -> Option<std::path::PathBuf> {
+        self.data_file_path(SESSION_FILE_NAME)
+    }
+
+    fn load_bandwidth_history(&mut self) -> Result<()> {
+        let Some(path) = self.data_file_path(BANDWIDTH_HISTORY_FILE_NAME) else {
+            return Ok(());
+        };
+        match std::fs::read(&path) {
+            Ok(json) => self.bandwidth_history = serde_json::from_slice(&json)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
         }
 
         Ok(())
     }
 
-    fn connect_with_device_info(&mut self, device_info: DeviceInfo) -> Result<()> {
-        let device = self.cast_ctx.create_device_from_info(device_info);
-        self.current_device_id += 1;
-        device
-            .connect(
-                None,
-                Arc::new(mcore::DeviceHandler::new(
-                    self.current_device_id,
-                    self.event_tx.clone(),
-                )),
-                1000,
-            )
-            .unwrap();
-        self.active_device = Some(device);
-        self.ui_weak.upgrade_in_event_loop(|ui| {
+    /// Remember the bitrate congestion control settled on for the active receiver
+    fn record_bandwidth(&mut self) {
+        let bitrate = CAPTURE_STATS.encoder_bitrate.load(Ordering::Relaxed);
+        let Some(device) = self
+            .active_device
+            .as_ref()
+            .filter(|_| self.tx_sink.is_some())
+        else {
+            return;
+        };
+        if bitrate == 0 {
+            return;
+        }
+
+        debug!(bitrate, "Recording bandwidth");
+        self.bandwidth_history.insert(device.name(), bitrate);
+        let Some(path) = self.data_file_path(BANDWIDTH_HISTORY_FILE_NAME) else {
+            return;
+        };
+        let res = serde_json::to_vec(&self.bandwidth_history)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(&path, json)?));
+        log_err!(res, "Failed to save bandwidth history");
+    }
+
+    /// Show what the connected receiver's last cast could sustain on the settings screen
+    fn update_recommended_settings(&self) -> Result<()> {
+        let recommendation = self
+            .active_device
+            .as_ref()
+            .and_then(|device| self.bandwidth_history.get(&device.name()))
+            .map(|&bitrate| {
+                let rec = mcore::transmission::recommend_video_settings(bitrate);
+                format!(
+                    "{}p at {} fps, the last cast to this receiver reached {:.1} Mbit/s",
+                    rec.height,
+                    rec.framerate,
+                    bitrate as f64 / 1_000_000.0
+                )
+            })
+            .unwrap_or_default();
+        self.ui_weak.upgrade_in_event_loop(move |ui| {
             ui.global::<Bridge>()
-                .invoke_change_state(AppState::Connecting);
+                .set_recommended_settings(recommendation.into());
         })?;
 
         Ok(())
     }
 
-    /// Returns `true` if the event loop should quit
-    async fn handle_event(&mut self, event: Event) -> Result<ShouldQuit> {
-        debug!("Handling event: {event:?}");
+    fn save_session(&self) {
+        let (Some(path), Some(device), Some(settings)) = (
+            self.session_path(),
+            self.active_device.as_ref(),
+            self.cast_settings.clone(),
+        ) else {
+            return;
+        };
 
-        match event {
-            Event::EndSession { .. } => {
-                self.ui_weak.upgrade_in_event_loop(|ui| {
-                    ui.global::<Bridge>()
-                        .invoke_change_state(AppState::Disconnected);
-                })?;
+        let session = SavedSession::new(&device.get_device_info(), settings);
+        let res = serde_json::to_vec(&session)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(&path, json)?));
+        log_err!(res, "Failed to save session");
+    }
 
-                self.stop_cast(true).await?;
-            }
-            Event::ConnectToDevice(device_name) => {
-                if let Some(device_info) = self.devices.get(&device_name) {
-                    self.connect_with_device_info(device_info.clone())?;
-                } else {
-                    error!("No device with name `{device_name}` found");
+    /// Offer to resume a session that was interrupted by the process being killed
+    fn restore_session(&mut self) -> Result<()> {
+        let Some(path) = self.session_path() else {
+            return Ok(());
+        };
+        let json = match std::fs::read(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let session = match serde_json::from_slice::<SavedSession>(&json) {
+            Ok(session) => session,
+            Err(err) => {
+                warn!(?err, "Discarding unreadable saved session");
+                return self.forget_session();
+            }
+        };
+
+        debug!(?session, "Found interrupted session");
+        let name = session.device_name.clone();
+        self.saved_session = Some(session);
+        self.ui_weak.upgrade_in_event_loop(move |ui| {
+            ui.global::<Bridge>().set_resumable_session(name.into());
+        })?;
+
+        Ok(())
+    }
+
+    fn forget_session(&mut self) -> Result<()> {
+        if self.saved_session.take().is_some() {
+            self.ui_weak.upgrade_in_event_loop(|ui| {
+                ui.global::<Bridge>()
+                    .set_resumable_session(Default::default());
+            })?;
+        }
+        if let Some(path) = self.session_path() {
+            match std::fs::remove_file(path) {
+                Ok(()) => debug!("Removed saved session"),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                Err(err) => error!(?err, "Failed to remove saved session"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expire receivers that silently vanished, e.g. because they lost power, which discovery
+    /// never reports. The remaining online receivers are probed so the ones that are still
+    /// around are seen again before the next sweep.
+    fn sweep_devices(&mut self) -> Result<()> {
+        let active_id = self.active_device_id.clone();
+        let mut changed = false;
+        self.devices.retain(|id, device| {
+            if active_id.as_ref() == Some(id) {
+                return true;
+            }
+            let elapsed = device.last_seen.elapsed();
+            if device.online && device.lost_at.is_none() && elapsed >= DEVICE_EXPIRY {
+                debug!(name = device.info.name, ?elapsed, "Device expired");
+                device.online = false;
+                changed = true;
+            } else if !device.online && elapsed >= OFFLINE_DEVICE_EXPIRY {
+                debug!(name = device.info.name, ?elapsed, "Removing offline device");
+                changed = true;
+                return false;
+            }
+            true
+        });
+
+        for (id, device) in self.devices.iter().filter(|(_, device)| device.online) {
+            self.probe_device(id, &device.info);
+        }
+
+        if changed {
+            self.update_receivers_in_ui()?;
+            // Receivers that are back by now are found again
+            let android_app = self.android_app.clone();
+            slint::invoke_from_event_loop(move || {
+                call_java_method_no_args(&android_app, JavaMethod::RestartDiscovery);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Tear down the screen capture pipeline while staying connected to the receiver, e.g. before
+    /// loading some other content on it.
+    fn stop_screen_cast(&mut self) -> Result<()> {
+        self.record_bandwidth();
+        self.cast_deadline = None;
+        self.play_url_fallback = None;
+        self.superseded_source_url = None;
+        *AUDIO_SRC.lock() = None;
+        if let Some(mut tx_sink) = self.tx_sink.take() {
+            debug!("Stopping screen cast");
+            tx_sink.shutdown();
+            let android_app = self.android_app.clone();
+            slint::invoke_from_event_loop(move || {
+                call_java_method_no_args(&android_app, JavaMethod::StopCapture);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop mirroring the screen to show other media on the same receiver. The receiver stays
+    /// connected, so the UI goes back to picking what to cast next.
+    fn replace_screen_cast(&mut self) -> Result<()> {
+        self.stop_screen_cast()?;
+        // The receiver is about to load other content, nothing it reports belongs to the stream
+        self.receiver_playback_state = device::PlaybackState::Idle;
+        self.our_source_url = None;
+        if matches!(
+            self.state.state,
+            AppState::WaitingForMedia | AppState::Casting
+        ) {
+            self.change_state(AppState::SelectingSettings)?;
+        }
+
+        Ok(())
+    }
+
+    async fn stop_cast(&mut self, stop_playback: bool) -> Result<()> {
+        self.record_bandwidth();
+        self.cast_deadline = None;
+        self.play_url_fallback = None;
+        self.superseded_source_url = None;
+        let android_app = self.android_app.clone();
+        slint::invoke_from_event_loop(move || {
+            call_java_method_no_args(&android_app, JavaMethod::StopCapture);
+        })?;
+
+        self.active_device_id = None;
+        if let Some(active_device) = self.active_device.take() {
+            // The receiver does not report anything when it was not playing to begin with
+            let stopped_rx = (stop_playback
+                && self.receiver_playback_state != device::PlaybackState::Idle)
+                .then(|| {
+                    let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel();
+                    self.pending_stop = Some(PendingStop {
+                        device_id: self.current_device_id,
+                        stopped_tx,
+                    });
+                    stopped_rx
+                });
+            tokio::spawn(async move {
+                if stop_playback {
+                    debug!("Stopping playback");
+                    match active_device.stop_playback() {
+                        Ok(()) => {
+                            if let Some(stopped_rx) = stopped_rx {
+                                if tokio::time::timeout(STOP_PLAYBACK_TIMEOUT, stopped_rx)
+                                    .await
+                                    .is_err()
+                                {
+                                    warn!("Receiver did not confirm that playback stopped");
+                                }
+                            }
+                        }
+                        Err(err) => error!(?err, "Failed to stop playback"),
+                    }
+                }
+                debug!("Disconnecting from active device");
+                log_err!(
+                    active_device.disconnect(),
+                    "Failed to disconnect from active device"
+                );
+            });
+        }
+
+        *AUDIO_SRC.lock() = None;
+        if let Some(mut tx_sink) = self.tx_sink.take() {
+            tx_sink.shutdown();
+        }
+        self.discard_prepared_cast();
+        self.pending_resume = None;
+        self.forget_session()?;
+
+        self.receiver_playback_state = device::PlaybackState::Idle;
+        self.hostname_advertiser = None;
+        self.connection_stage = None;
+
+        Ok(())
+    }
+
+    fn send_play_message(&mut self, bound_port_v4: u16, bound_port_v6: u16) -> Result<()> {
+        let Some(addr) = self.local_address.as_ref() else {
+            error!("Local address is missing");
+            return Ok(());
+        };
+        let bound_port = match addr {
+            fcast_sender_sdk::IpAddr::V4 { .. } => bound_port_v4,
+            fcast_sender_sdk::IpAddr::V6 { .. } => bound_port_v6,
+        };
+
+        if self.hostname_advertiser.is_none() {
+            self.hostname_advertiser = match HostnameAdvertiser::new(&self.mdns_name, bound_port) {
+                Ok(advertiser) => Some(advertiser),
+                Err(err) => {
+                    error!(?err, "Failed to advertise hostname, falling back to IP");
+                    None
+                }
+            };
+        }
+
+        let tx_sink = self.tx_sink.as_ref().unwrap();
+        let (content_type, url) = tx_sink.get_play_msg(addr.into(), bound_port);
+        match self.hostname_advertiser.as_ref() {
+            Some(advertiser) => {
+                let (host_content_type, hostname_url) =
+                    tx_sink.get_play_msg_for_host(advertiser.hostname(), bound_port);
+                self.play_url_fallback = Some(PlayUrlFallback {
+                    content_type,
+                    url,
+                    hostname_url: hostname_url.clone(),
+                    deadline: tokio::time::Instant::now() + HOSTNAME_PLAY_TIMEOUT,
+                });
+                self.load_play_url(host_content_type, hostname_url)
+            }
+            None => self.load_play_url(content_type, url),
+        }
+    }
+
+    /// Send the IP based play URL if the receiver could not play the hostname based one
+    fn fall_back_to_ip_play_url(&mut self) -> Result<()> {
+        let Some(fallback) = self.play_url_fallback.take() else {
+            return Ok(());
+        };
+        warn!(
+            hostname_url = fallback.hostname_url,
+            "Receiver did not play the hostname based URL, sending the IP based one"
+        );
+        self.superseded_source_url = Some(fallback.hostname_url);
+        self.load_play_url(fallback.content_type, fallback.url)?;
+
+        Ok(())
+    }
+
+    fn load_play_url(&mut self, content_type: String, url: String) -> Result<()> {
+        debug!(content_type, url, "Sending play message");
+        self.our_source_url = Some(url.clone());
+
+        match self.active_device.as_ref() {
+            Some(device) => {
+                device.load(device::LoadRequest::Url {
+                    content_type,
+                    url,
+                    resume_position: None,
+                    speed: None,
+                    volume: None,
+                    metadata: None,
+                    request_headers: None,
+                })?;
+            }
+            None => error!("Active device is missing, cannot send play message"),
+        }
+
+        Ok(())
+    }
+
+    /// Let a stop that is waiting on the receiver continue once it reports that it is no longer
+    /// playing, see [`Application::stop_cast`].
+    fn resolve_pending_stop(&mut self, device_id: usize, event: &DeviceEvent) {
+        let stopped = matches!(
+            event,
+            DeviceEvent::PlaybackStateChanged(device::PlaybackState::Idle)
+                | DeviceEvent::StateChanged(device::DeviceConnectionState::Disconnected)
+        );
+        match self.pending_stop.take() {
+            Some(pending) if stopped && pending.device_id == device_id => {
+                let _ = pending.stopped_tx.send(());
+            }
+            pending => self.pending_stop = pending,
+        }
+    }
+
+    /// Show `status` while connecting, or clear it with `None` once connected.
+    fn set_connection_status(&mut self, status: Option<(ConnectionStage, String)>) -> Result<()> {
+        self.connection_stage = status.as_ref().map(|(stage, _)| *stage);
+        let message = match status {
+            Some((stage, message)) => {
+                self.post_java_event(JavaEvent::ConnectionStatus {
+                    stage: connection_stage_name(stage),
+                    receiver_name: self
+                        .active_device
+                        .as_ref()
+                        .map(|device| device.name())
+                        .unwrap_or_default(),
+                    message: message.clone(),
+                })?;
+                message
+            }
+            None => String::new(),
+        };
+        self.ui_weak.upgrade_in_event_loop(move |ui| {
+            ui.global::<Bridge>().set_connection_status(message.into());
+        })?;
+
+        Ok(())
+    }
+
+    fn set_connection_error(&self, error: String) -> Result<()> {
+        if !error.is_empty() {
+            self.post_java_event(JavaEvent::ConnectionError {
+                message: error.clone(),
+            })?;
+        }
+        self.ui_weak.upgrade_in_event_loop(move |ui| {
+            ui.global::<Bridge>().set_connection_error(error.into());
+        })?;
+
+        Ok(())
+    }
+
+    async fn connect_with_device_info(
+        &mut self,
+        id: Option<DeviceId>,
+        device_info: DeviceInfo,
+    ) -> Result<()> {
+        let name = device_info.name.clone();
+        self.set_connection_error(String::new())?;
+        self.forget_session()?;
+        self.pending_resume = None;
+
+        if device_info.addresses.is_empty() || device_info.port == 0 {
+            error!(?device_info, "Device is missing address or port");
+            self.set_connection_error(format!("{name} did not advertise a usable address"))?;
+            return Ok(());
+        }
+
+        // Only one receiver is talked to at a time, and `Connecting` can only be entered from
+        // `Disconnected`
+        if self.active_device.is_some() || self.tx_sink.is_some() {
+            debug!("Leaving the current receiver to connect to {name}");
+            self.change_state(AppState::Disconnected)?;
+            self.stop_cast(true).await?;
+        }
+
+        let device = self.cast_ctx.create_device_from_info(device_info.clone());
+        self.current_device_id += 1;
+        self.connect_attempts = 0;
+        if let Err(err) = device.connect(
+            None,
+            Arc::new(mcore::DeviceHandler::new(
+                self.current_device_id,
+                self.event_tx.clone(),
+            )),
+            1000,
+        ) {
+            error!(?err, "Failed to connect to device");
+            self.set_connection_error(format!("Failed to connect to {name}: {err}"))?;
+            self.change_state(AppState::Disconnected)?;
+            return Ok(());
+        }
+        self.active_device = Some(device);
+        self.active_device_id = id;
+        self.change_state(AppState::Connecting)?;
+        self.set_connection_status(Some((
+            ConnectionStage::Resolving,
+            format!("Looking for {name} on the network"),
+        )))?;
+
+        // Runs next to the device's own connection attempts, an address that answers means the
+        // device is only waiting for the receiver to accept the session
+        let event_tx = self.event_tx.clone();
+        let device_id = self.current_device_id;
+        tokio::spawn(async move {
+            let result =
+                mcore::reachability::probe(&device_info.addresses, device_info.port, PROBE_TIMEOUT)
+                    .await;
+            log_err!(
+                event_tx.send(Event::ConnectProbed { device_id, result }),
+                "Failed to send connect probed event"
+            );
+        });
+
+        Ok(())
+    }
+
+    /// Returns `true` if the event loop should quit
+    async fn handle_event(&mut self, event: Event) -> Result<ShouldQuit> {
+        debug!("Handling event: {event:?}");
+
+        match event {
+            Event::EndSession { .. } => {
+                self.change_state(AppState::Disconnected)?;
+
+                self.stop_cast(true).await?;
+            }
+            Event::ConnectToDevice(device_id) => {
+                let id = DeviceId(device_id);
+                if let Some(device) = self.devices.get(&id) {
+                    self.connect_with_device_info(Some(id.clone()), device.info.clone())
+                        .await?;
+                } else {
+                    error!("No device with id `{}` found", id.0);
+                }
+            }
+            Event::SignallerStarted {
+                sink_id,
+                bound_port_v4,
+                bound_port_v6,
+            } => {
+                let current_sink = self
+                    .tx_sink
+                    .as_ref()
+                    .or(self.prepared_cast.as_ref().map(|prepared| &prepared.sink));
+                if current_sink.map(CastSink::id) != Some(sink_id) {
+                    debug!(sink_id, "Ignoring signaller of a discarded sink");
+                    return Ok(ShouldQuit::No);
+                }
+                // A prepared pipeline is started before the user picked to cast
+                if self.state.state == AppState::Casting {
+                    self.send_play_message(bound_port_v4, bound_port_v6)?;
+                } else {
+                    self.signaller_ports = Some((bound_port_v4, bound_port_v6));
+                }
+            }
+            Event::Quit => return Ok(ShouldQuit::Yes),
+            Event::ServiceFound {
+                instance,
+                device_info,
+            } => {
+                self.add_or_update_device(DeviceId::from_service_instance(&instance), device_info)?
+            }
+            Event::ServiceLost(instance) => {
+                if let Some(device) = self
+                    .devices
+                    .get_mut(&DeviceId::from_service_instance(&instance))
+                {
+                    if device.online && device.lost_at.is_none() {
+                        device.lost_at = Some(std::time::Instant::now());
+                        let event_tx = self.event_tx.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(REMOVAL_DEBOUNCE).await;
+                            log_err!(
+                                event_tx.send(Event::DeviceRemovalSettled(instance)),
+                                "Failed to send device removal settled event"
+                            );
+                        });
+                    }
+                } else {
+                    debug!(instance, "Tried to remove device but it was not found");
                 }
             }
-            Event::SignallerStarted { bound_port_v4, bound_port_v6 } => {
-                let Some(addr) = self.local_address.as_ref() else {
-                    error!("Local address is missing");
+            // Only sent by the SDK's discoverer, receivers are discovered through `NsdManager`
+            // on Android, see `Event::ServiceFound`
+            Event::DeviceAvailable(_) | Event::DeviceRemoved(_) | Event::DeviceChanged(_) => (),
+            Event::DeviceRemovalSettled(instance) => {
+                let Some(device) = self
+                    .devices
+                    .get_mut(&DeviceId::from_service_instance(&instance))
+                else {
                     return Ok(ShouldQuit::No);
                 };
-                let bound_port = match addr {
-                    fcast_sender_sdk::IpAddr::V4 { .. } => bound_port_v4,
-                    fcast_sender_sdk::IpAddr::V6 { .. } => bound_port_v6,
+                // Ignore timers from an earlier loss the device has since recovered from
+                let Some(lost_at) = device
+                    .lost_at
+                    .filter(|lost_at| lost_at.elapsed() >= REMOVAL_DEBOUNCE)
+                else {
+                    return Ok(ShouldQuit::No);
                 };
+                debug!(instance, "Device is gone");
+                device.online = false;
+                device.last_seen = lost_at;
+                device.lost_at = None;
+                self.update_receivers_in_ui()?;
+            }
+            Event::NetworkChanged {
+                network_type,
+                has_internet,
+            } => {
+                let previous = self.network.replace((network_type, has_internet));
+                // Capabilities are reported again for every minor change, e.g. signal strength
+                if previous.is_none() || previous == self.network {
+                    return Ok(ShouldQuit::No);
+                }
+                debug!(?network_type, has_internet, ?previous, "Network changed");
 
-                let (content_type, url) = self
-                    .tx_sink
-                    .as_ref()
-                    .unwrap()
-                    .get_play_msg(addr.into(), bound_port);
-
-                debug!(content_type, url, "Sending play message");
-                self.our_source_url = Some(url.clone());
-
-                match self.active_device.as_ref() {
-                    Some(device) => {
-                        device.load(device::LoadRequest::Url {
-                            content_type,
-                            url,
-                            resume_position: None,
-                            speed: None,
-                            volume: None,
-                            metadata: None,
-                            request_headers: None,
-                        })?;
+                // Receivers are rediscovered and probed again on the new network
+                for device in self.devices.values_mut() {
+                    if device.online {
+                        device.online = false;
+                        device.last_seen = std::time::Instant::now();
                     }
-                    None => error!("Active device is missing, cannot send play message"),
+                    device.lost_at = None;
+                    device.reachability = Reachability::Unknown;
                 }
+                self.update_receivers_in_ui()?;
 
-                // self.ui_weak.upgrade_in_event_loop(|ui| {
-                //     ui.global::<Bridge>().invoke_change_state(AppState::Casting);
-                // })?;
+                if network_type != mcore::NetworkType::None {
+                    let android_app = self.android_app.clone();
+                    slint::invoke_from_event_loop(move || {
+                        call_java_method_no_args(&android_app, JavaMethod::RestartDiscovery);
+                    })?;
+                }
             }
-            Event::Quit => return Ok(ShouldQuit::Yes),
-            Event::DeviceAvailable(device_info) => self.add_or_update_device(device_info)?,
-            Event::DeviceRemoved(device_name) => {
-                if self.devices.remove(&device_name).is_some() {
-                    self.update_receivers_in_ui()?;
-                } else {
-                    debug!(device_name, "Tried to remove device but it was not found");
+            Event::ConnectProbed { device_id, result } => {
+                let waiting = matches!(
+                    self.connection_stage,
+                    Some(ConnectionStage::Resolving | ConnectionStage::Connecting)
+                );
+                if device_id == self.current_device_id && waiting {
+                    match result {
+                        Some(probe) => {
+                            debug!(?probe, "Receiver answered, waiting for the handshake");
+                            let name = self
+                                .active_device
+                                .as_ref()
+                                .map(|device| device.name())
+                                .unwrap_or_default();
+                            self.set_connection_status(Some((
+                                ConnectionStage::Handshaking,
+                                format!("Waiting for {name} to accept the connection"),
+                            )))?;
+                        }
+                        None => debug!("None of the receiver's addresses answered the probe"),
+                    }
                 }
             }
-            Event::DeviceChanged(device_info) => self.add_or_update_device(device_info)?,
+            Event::DeviceProbed { instance, result } => {
+                let Some(device) = self
+                    .devices
+                    .get_mut(&DeviceId::from_service_instance(&instance))
+                else {
+                    return Ok(ShouldQuit::No);
+                };
+                device.reachability = match result {
+                    Some(probe) => {
+                        if device.online {
+                            device.last_seen = std::time::Instant::now();
+                        }
+                        // Try the address that answered first when connecting
+                        let addrs = &mut device.info.addresses;
+                        if let Some(idx) = addrs.iter().position(|a| *a == probe.addr) {
+                            addrs[..=idx].rotate_right(1);
+                        }
+                        Reachability::Reachable(probe.rtt)
+                    }
+                    None => Reachability::Unreachable,
+                };
+                self.update_receivers_in_ui()?;
+            }
             Event::FromDevice { id, event } => {
+                self.resolve_pending_stop(id, &event);
                 if id != self.current_device_id {
                     debug!(
                         "Got message from old device (id: {id} current: {})",
@@ -265,11 +1684,80 @@ impl Application {
                             match device_connection_state {
                                 device::DeviceConnectionState::Connected { local_addr, .. } => {
                                     self.local_address = Some(local_addr);
-
-                                    self.ui_weak.upgrade_in_event_loop(|ui| {
-                                        ui.global::<Bridge>()
-                                            .invoke_change_state(AppState::SelectingSettings);
+                                    let whep_supported =
+                                        self.active_device.as_ref().is_some_and(|device| {
+                                            device.supports_feature(DeviceFeature::WhepStreaming)
+                                        });
+                                    debug!(whep_supported, "Device connected");
+                                    self.ui_weak.upgrade_in_event_loop(move |ui| {
+                                        ui.global::<Bridge>().set_whep_supported(whep_supported);
                                     })?;
+
+                                    self.set_connection_status(None)?;
+                                    self.update_recommended_settings()?;
+                                    // Also reported after reconnecting in the middle of a cast
+                                    if self.state.state == AppState::Connecting {
+                                        self.change_state(AppState::SelectingSettings)?;
+                                    }
+                                    if self.state.state == AppState::SelectingSettings {
+                                        // HLS pipelines can't be prepared, the segments written
+                                        // before the receiver loads the playlist would only
+                                        // delay the stream further
+                                        if whep_supported {
+                                            self.prepare_cast();
+                                        }
+                                        if let Some(settings) = self.pending_resume.take() {
+                                            debug!(?settings, "Resuming cast");
+                                            log_err!(
+                                                self.event_tx.send(Event::StartCast {
+                                                    scale_width: settings.scale_width,
+                                                    scale_height: settings.scale_height,
+                                                    max_framerate: settings.max_framerate,
+                                                    record: settings.record,
+                                                    debug_overlay: settings.debug_overlay,
+                                                    latency_preset: settings.latency_preset,
+                                                    ice: settings.ice,
+                                                    max_duration: settings.max_duration,
+                                                }),
+                                                "Failed to send start cast event"
+                                            );
+                                        }
+                                    }
+                                }
+                                device::DeviceConnectionState::Reconnecting
+                                    if self.state.state == AppState::Connecting =>
+                                {
+                                    self.connect_attempts += 1;
+                                    if self.connect_attempts >= MAX_CONNECT_ATTEMPTS {
+                                        self.set_connection_error(
+                                            "Could not reach the receiver, make sure it is on the same network"
+                                                .to_owned(),
+                                        )?;
+                                        self.change_state(AppState::Disconnected)?;
+                                        self.stop_cast(false).await?;
+                                    } else {
+                                        self.set_connection_status(Some((
+                                            ConnectionStage::Connecting,
+                                            format!(
+                                                "Receiver is not responding, retrying ({}/{MAX_CONNECT_ATTEMPTS})",
+                                                self.connect_attempts
+                                            ),
+                                        )))?;
+                                    }
+                                }
+                                device::DeviceConnectionState::Connecting
+                                    if self.connection_stage
+                                        == Some(ConnectionStage::Resolving) =>
+                                {
+                                    let name = self
+                                        .active_device
+                                        .as_ref()
+                                        .map(|device| device.name())
+                                        .unwrap_or_default();
+                                    self.set_connection_status(Some((
+                                        ConnectionStage::Connecting,
+                                        format!("Connecting to {name}"),
+                                    )))?;
                                 }
                                 _ => (),
                             }
@@ -278,7 +1766,9 @@ impl Application {
                             if self.tx_sink.is_some() {
                                 match new_source {
                                     fcast_sender_sdk::device::Source::Url { ref url, .. } => {
-                                        if Some(url) != self.our_source_url.as_ref() {
+                                        if Some(url) != self.our_source_url.as_ref()
+                                            && Some(url) != self.superseded_source_url.as_ref()
+                                        {
                                             // At this point the receiver has stopped playing our stream
                                             debug!(
                                                 ?new_source,
@@ -291,108 +1781,270 @@ impl Application {
                                 }
                             }
                         }
+                        DeviceEvent::PlaybackStateChanged(new_state) => {
+                            if new_state == device::PlaybackState::Playing
+                                && self.play_url_fallback.take().is_some()
+                            {
+                                debug!("Receiver is playing the hostname based URL");
+                            }
+                            if let Some(tx_sink) = self.tx_sink.as_ref() {
+                                match new_state {
+                                    device::PlaybackState::Paused => {
+                                        debug!("Receiver paused playback, pausing pipeline");
+                                        tx_sink.pause();
+                                    }
+                                    device::PlaybackState::Playing
+                                        if self.receiver_playback_state
+                                            == device::PlaybackState::Paused =>
+                                    {
+                                        debug!("Receiver resumed playback, resuming pipeline");
+                                        tx_sink.resume();
+                                    }
+                                    device::PlaybackState::Idle
+                                        if self.receiver_playback_state
+                                            != device::PlaybackState::Idle =>
+                                    {
+                                        debug!("Receiver stopped playback, stopping cast");
+                                        self.change_state(AppState::Disconnected)?;
+                                        self.stop_cast(false).await?;
+                                        return Ok(ShouldQuit::No);
+                                    }
+                                    _ => (),
+                                }
+                            }
+
+                            self.receiver_playback_state = new_state;
+                        }
+                        DeviceEvent::PlaybackError(message) => {
+                            error!(message, "Receiver failed to play");
+                            self.fall_back_to_ip_play_url()?;
+                        }
                     }
                 }
             }
             Event::CaptureStopped => (),
             Event::CaptureCancelled => {
-                self.ui_weak.upgrade_in_event_loop(|ui| {
-                    ui.global::<Bridge>()
-                        .invoke_change_state(AppState::Disconnected);
-                })?;
+                self.change_state(AppState::Disconnected)?;
 
                 self.stop_cast(false).await?;
             }
             Event::QrScanResult(result) => {
                 match fcast_sender_sdk::device::device_info_from_url(result) {
                     Some(device_info) => {
-                        self.connect_with_device_info(device_info)?;
+                        self.connect_with_device_info(None, device_info).await?;
                     }
                     None => {
                         error!("QR code scan result is not a valid device");
                     }
                 }
             }
-            Event::CaptureStarted => {
-                let appsrc = gst_app::AppSrc::builder()
-                    .caps(
-                        &gst_video::VideoCapsBuilder::new()
-                            .format(gst_video::VideoFormat::I420)
-                            // .framerate(gst::Fraction::new(0, 1))
-                            .build(),
-                    )
-                    .is_live(true)
-                    .do_timestamp(true)
-                    .format(gst::Format::Time)
-                    .max_buffers(1)
-                    .build();
-
-                let mut caps = None::<gst::Caps>;
-                appsrc.set_callbacks(
-                    gst_app::AppSrcCallbacks::builder()
-                        .need_data(move |appsrc, _| {
-                            let frame = {
-                                let (lock, cvar) = &*FRAME_PAIR;
-                                let mut frame = lock.lock();
-                                while (*frame).is_none() {
-                                    cvar.wait(&mut frame);
-                                }
+            Event::ResumeSession => match self.saved_session.take() {
+                Some(session) => {
+                    let settings = session.settings;
+                    self.connect_with_device_info(None, session.device_info())
+                        .await?;
+                    if self.state.state == AppState::Connecting {
+                        self.pending_resume = Some(settings);
+                    }
+                }
+                None => error!("No session to resume"),
+            },
+            Event::SetGstDebug {
+                threshold,
+                stream_logs,
+            } => {
+                if !threshold.is_empty() {
+                    debug!(threshold, "Setting GStreamer debug threshold");
+                    gst::log::set_threshold_from_string(&threshold, true);
+                }
+                self.set_gst_log_streaming(stream_logs);
+
+                let categories = gst::DebugCategory::all_categories()
+                    .into_iter()
+                    .map(|category| GstDebugCategory {
+                        name: category.name().to_owned(),
+                        threshold: format!("{:?}", category.threshold()),
+                    })
+                    .collect();
+                self.post_java_event(JavaEvent::GstDebugCategories { categories })?;
+            }
+            Event::CastImage { path, content_type } => {
+                if self.active_device.is_none() || self.local_address.is_none() {
+                    error!("Not connected to a device, cannot cast image");
+                    return Ok(ShouldQuit::No);
+                }
 
-                                (*frame).take().unwrap()
-                            };
-
-                            use gst_video::prelude::*;
-
-                            let now_caps = gst_video::VideoInfo::builder(
-                                frame.format(),
-                                frame.width(),
-                                frame.height(),
-                            )
-                            .build()
-                            .unwrap()
-                            .to_caps()
-                            .unwrap();
-
-                            match &caps {
-                                Some(old_caps) => {
-                                    if *old_caps != now_caps {
-                                        appsrc.set_caps(Some(&now_caps));
-                                        caps = Some(now_caps);
-                                    }
-                                }
-                                None => {
-                                    appsrc.set_caps(Some(&now_caps));
-                                    caps = Some(now_caps);
-                                }
-                            }
+                self.replace_screen_cast()?;
 
-                            let _ = appsrc.push_buffer(frame.into_buffer());
-                        })
-                        .build(),
-                );
+                if self.image_server.is_none() {
+                    self.image_server = Some(ImageServer::new().await?);
+                }
+
+                let request = self
+                    .image_server
+                    .as_ref()
+                    .unwrap()
+                    .serve(
+                        &path,
+                        content_type.as_deref(),
+                        self.local_address.as_ref().unwrap().into(),
+                    )
+                    .await?;
+                if let device::LoadRequest::Image { url, .. } = &request {
+                    self.our_source_url = Some(url.clone());
+                }
+                self.active_device.as_ref().unwrap().load(request)?;
+            }
+            Event::CastText(text) => {
+                if self.active_device.is_none() {
+                    error!("Not connected to a device, cannot cast text");
+                    return Ok(ShouldQuit::No);
+                }
 
-                let source_config = SourceConfig::Video(mcore::VideoSource::Source(appsrc));
+                self.replace_screen_cast()?;
 
-                self.tx_sink = Some(mcore::transmission::WhepSink::new(
-                    source_config,
-                    self.event_tx.clone(),
-                    tokio::runtime::Handle::current(),
-                    1920,
-                    1080,
-                    30,
-                )?);
+                let text = text.trim().to_owned();
+                let is_url = text.starts_with("http://") || text.starts_with("https://");
+                // Receivers can't play what they don't know the type of, so such URLs are shown
+                // as text instead
+                let url_content_type = is_url.then(|| content_type_from_url(&text)).flatten();
+                if is_url && url_content_type.is_none() {
+                    debug!(url = text, "Unknown media type, casting the URL as text");
+                }
+                let request = if let Some(content_type) = url_content_type {
+                    self.our_source_url = Some(text.clone());
+                    device::LoadRequest::Url {
+                        content_type: content_type.to_owned(),
+                        url: text,
+                        resume_position: None,
+                        speed: None,
+                        volume: None,
+                        metadata: None,
+                        request_headers: None,
+                    }
+                } else {
+                    device::LoadRequest::Content {
+                        content_type: "text/plain".to_owned(),
+                        content: text,
+                        resume_position: 0.0,
+                        speed: None,
+                        volume: None,
+                        metadata: None,
+                        request_headers: None,
+                    }
+                };
 
-                self.ui_weak.upgrade_in_event_loop(|ui| {
-                    ui.global::<Bridge>().invoke_change_state(AppState::Casting);
-                })?;
+                debug!(?request, "Casting text");
+                self.active_device.as_ref().unwrap().load(request)?;
+            }
+            Event::CaptureStarted { with_audio } => {
+                // Frames left over from a previous cast would be shown out of order
+                while FRAME_QUEUE.1.try_recv().is_ok() {}
+                CAPTURE_STATS.reset();
+                *LAST_FRAME_INSTANT.lock() = None;
+                CAPTURE_LIVE.store(true, Ordering::Relaxed);
+
+                let on_cellular = matches!(self.network, Some((mcore::NetworkType::Cellular, _)));
+                let candidate_filter = self.ice_settings.candidate_filter(on_cellular);
+                let tx_sink = match self.prepared_cast.take() {
+                    // The prepared pipeline is built with the default options
+                    Some(prepared)
+                        if !with_audio
+                            && !self.hls_fallback
+                            && !self.debug_overlay
+                            && self.record_path.is_none()
+                            && self.latency_preset == LatencyPreset::default()
+                            && candidate_filter == CandidateFilter::default()
+                            && self.ice_settings.turn_server.is_none() =>
+                    {
+                        debug!("Using prepared pipeline");
+                        self.set_capture_latency(&prepared.appsrc);
+                        prepared.sink
+                    }
+                    prepared => {
+                        if let Some(mut prepared) = prepared {
+                            prepared.sink.shutdown();
+                            self.signaller_ports = None;
+                        }
+                        let options = CastOptions {
+                            record_to: self.record_path.take(),
+                            debug_overlay: self.debug_overlay,
+                            capture_stats: Arc::clone(&CAPTURE_STATS),
+                            preview_sink: None,
+                            latency_preset: self.latency_preset,
+                            candidate_filter,
+                            turn_server: self.ice_settings.turn_server.clone(),
+                        };
+                        self.create_cast_pipeline(with_audio, self.hls_fallback, options)?
+                            .sink
+                    }
+                };
+                self.tx_sink = Some(tx_sink);
+
+                self.change_state(AppState::Casting)?;
+                self.save_session();
+                self.cast_deadline = self
+                    .max_cast_duration
+                    .map(|duration| tokio::time::Instant::now() + duration);
+                if let Some((bound_port_v4, bound_port_v6)) = self.signaller_ports.take() {
+                    self.send_play_message(bound_port_v4, bound_port_v6)?;
+                }
             }
             Event::StartCast {
                 scale_width,
                 scale_height,
                 max_framerate,
+                record,
+                debug_overlay,
+                latency_preset,
+                ice,
+                max_duration,
             } => {
+                let capture = latency_preset.capture_config();
+                *CAPTURE_CONFIG.lock() = CaptureConfig {
+                    max_queued_frames: capture.max_queued_frames.clamp(1, MAX_FRAME_QUEUE_CAPACITY),
+                    min_frame_interval: min_frame_interval(max_framerate),
+                    ..capture
+                };
+                // Decided before capture starts so the right pipeline is built for it
+                self.hls_fallback = !self
+                    .active_device
+                    .as_ref()
+                    .is_some_and(|device| device.supports_feature(DeviceFeature::WhepStreaming));
+                if self.hls_fallback {
+                    debug!("Receiver does not support WHEP streaming, casting over HLS");
+                }
+                self.latency_preset = latency_preset;
+                self.debug_overlay = debug_overlay;
+                self.capture_framerate = max_framerate;
+                self.ice_settings = ice.clone();
+                self.max_cast_duration = max_duration;
+                self.cast_settings = Some(CastSettings {
+                    scale_width,
+                    scale_height,
+                    max_framerate,
+                    record,
+                    debug_overlay,
+                    latency_preset,
+                    ice,
+                    max_duration,
+                });
+                self.record_path = None;
+                if record {
+                    match self.android_app.external_data_path() {
+                        Some(dir) => {
+                            let secs = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map(|d| d.as_secs())
+                                .unwrap_or_default();
+                            self.record_path = Some(dir.join(format!("fcast-cast-{secs}.mkv")));
+                        }
+                        None => error!("External data path is missing, cannot record cast"),
+                    }
+                }
+
                 let android_app = self.android_app.clone();
-                self.ui_weak.upgrade_in_event_loop(move |ui| {
+                slint::invoke_from_event_loop(move || {
                     let vm = unsafe {
                         let ptr = android_app.vm_as_ptr() as *mut jni::sys::JavaVM;
                         assert!(!ptr.is_null(), "JavaVM ptr is null");
@@ -428,33 +2080,70 @@ impl Application {
                         },
                         Err(err) => error!(?err, "Failed to get env from VM"),
                     }
-
-                    ui.global::<Bridge>()
-                        .invoke_change_state(AppState::WaitingForMedia);
                 })?;
+
+                self.change_state(AppState::WaitingForMedia)?;
             }
         }
 
         Ok(ShouldQuit::No)
     }
 
-    pub async fn run_event_loop(
-        mut self,
-        mut event_rx: tokio::sync::mpsc::UnboundedReceiver<Event>,
-    ) -> Result<()> {
+    pub async fn run_event_loop(mut self, mut event_rx: EventReceiver) -> Result<()> {
         tracing_gstreamer::integrate_events();
         gst::log::remove_default_log_function();
         gst::log::set_default_threshold(gst::DebugLevel::Fixme);
         gst::init().unwrap();
         debug!("GStreamer version: {:?}", gst::version());
 
+        log_err!(self.restore_session(), "Failed to restore session");
+        log_err!(
+            self.load_bandwidth_history(),
+            "Failed to load bandwidth history"
+        );
+
         // self.add_or_update_device(fcast_sender_sdk::device::DeviceInfo::fcast("Localhost for android emulator".to_owned(), vec![fcast_sender_sdk::IpAddr::v4(10, 0, 2, 2)], 46899))?;
 
+        let mut device_sweep = tokio::time::interval_at(
+            tokio::time::Instant::now() + DEVICE_SWEEP_INTERVAL,
+            DEVICE_SWEEP_INTERVAL,
+        );
+        let mut gst_log_flush = tokio::time::interval(GST_LOG_FLUSH_INTERVAL);
+
         loop {
-            let Some(event) = event_rx.recv().await else {
+            let event = tokio::select! {
+                event = event_rx.recv() => event,
+                // A failing timer must not take the whole event loop down
+                _ = device_sweep.tick() => {
+                    log_err!(self.sweep_devices(), "Failed to sweep devices");
+                    continue;
+                }
+                _ = gst_log_flush.tick() => {
+                    log_err!(self.flush_gst_log(), "Failed to flush GStreamer log");
+                    continue;
+                }
+                _ = sleep_until(self.cast_deadline) => {
+                    log_err!(
+                        self.stop_cast_at_time_limit().await,
+                        "Failed to stop cast at its time limit"
+                    );
+                    continue;
+                }
+                _ = sleep_until(self.play_url_fallback.as_ref().map(|f| f.deadline)) => {
+                    log_err!(
+                        self.fall_back_to_ip_play_url(),
+                        "Failed to fall back to the IP based play URL"
+                    );
+                    continue;
+                }
+            };
+            let Some(event) = event else {
                 debug!("No more events");
                 break;
             };
+            CAPTURE_STATS
+                .dropped_events
+                .store(event_rx.dropped(), Ordering::Relaxed);
 
             if self.handle_event(event).await? == ShouldQuit::Yes {
                 break;
@@ -478,29 +2167,104 @@ fn android_main(app: slint::android::AndroidApp) {
 
     slint::android::init(app).unwrap();
 
-    let ui = MainWindow::new().unwrap();
-
     let runtime = tokio::runtime::Runtime::new().unwrap();
 
-    let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (event_tx, event_rx) = mcore::event_bus::channel();
+    *GLOB_EVENT_TX.lock() = Some(event_tx.clone());
 
-    ui.global::<Bridge>().on_connect_receiver({
-        let event_tx = event_tx.clone();
-        move |device_name| {
-            event_tx
-                .send(Event::ConnectToDevice(device_name.to_string()))
-                .unwrap();
-        }
+    let headless = HEADLESS.load(Ordering::Relaxed);
+    debug!(headless, "Starting");
+    let ui = (!headless).then(|| create_main_window(&app_clone, &event_tx));
+    let ui_weak = ui.as_ref().map(|ui| ui.as_weak()).unwrap_or_default();
+
+    let event_tx_clone = event_tx.clone();
+    let app_jh = runtime.spawn(async move {
+        Application::new(ui_weak, event_tx_clone, app_clone)
+            .await
+            .unwrap()
+            .run_event_loop(event_rx)
+            .await
+            .unwrap();
+    });
+
+    match ui {
+        Some(ui) => ui.run().unwrap(),
+        // Keeps running the closures the application posts to the main thread
+        None => slint::run_event_loop_until_quit().unwrap(),
+    }
+
+    runtime.spawn(async move {
+        event_tx.send(Event::Quit).unwrap();
+        app_jh.await.unwrap();
+    });
+
+    debug!("Finished");
+}
+
+fn create_main_window(
+    android_app: &slint::android::AndroidApp,
+    event_tx: &EventSender,
+) -> MainWindow {
+    let ui = MainWindow::new().unwrap();
+
+    ui.global::<Bridge>().on_connect_receiver({
+        let event_tx = event_tx.clone();
+        move |device_id| {
+            event_tx
+                .send(Event::ConnectToDevice(device_id.to_string()))
+                .unwrap();
+        }
+    });
+
+    ui.global::<Bridge>().on_resume_session({
+        let event_tx = event_tx.clone();
+        move || {
+            event_tx.send(Event::ResumeSession).unwrap();
+        }
     });
 
     ui.global::<Bridge>().on_start_casting({
         let event_tx = event_tx.clone();
-        move |scale_width: i32, scale_height: i32, max_framerate: i32| {
+        move |scale_width: i32,
+              scale_height: i32,
+              max_framerate: i32,
+              record: bool,
+              debug_overlay: bool,
+              latency_preset: i32,
+              no_host_on_cellular: bool,
+              no_mdns: bool,
+              turn_server: slint::SharedString,
+              time_limit: i32| {
+            // Indices of LatencyPresetPicker
+            let latency_preset = match latency_preset {
+                0 => LatencyPreset::UltraLow,
+                2 => LatencyPreset::Quality,
+                _ => LatencyPreset::Balanced,
+            };
+            // Indices of TimeLimitPicker
+            let max_duration = match time_limit {
+                1 => Some(15),
+                2 => Some(30),
+                3 => Some(60),
+                4 => Some(120),
+                _ => None,
+            }
+            .map(|minutes| std::time::Duration::from_secs(minutes * 60));
             event_tx
                 .send(Event::StartCast {
                     scale_width: scale_width as u32,
                     scale_height: scale_height as u32,
                     max_framerate: max_framerate as u32,
+                    record,
+                    debug_overlay,
+                    latency_preset,
+                    ice: IceSettings {
+                        no_host_on_cellular,
+                        no_mdns,
+                        turn_server: Some(turn_server.trim().to_owned())
+                            .filter(|server| !server.is_empty()),
+                    },
+                    max_duration,
                 })
                 .unwrap();
         }
@@ -516,32 +2280,143 @@ fn android_main(app: slint::android::AndroidApp) {
     });
 
     ui.global::<Bridge>().on_scan_qr({
-        let android_app = app_clone.clone();
+        let android_app = android_app.clone();
         move || {
             call_java_method_no_args(&android_app, JavaMethod::ScanQr);
         }
     });
 
-    let ui_weak = ui.as_weak();
+    ui.global::<Bridge>().on_cast_text({
+        let event_tx = event_tx.clone();
+        move |text| {
+            event_tx.send(Event::CastText(text.to_string())).unwrap();
+        }
+    });
 
-    let event_tx_clone = event_tx.clone();
-    let app_jh = runtime.spawn(async move {
-        Application::new(ui_weak, event_tx_clone, app_clone)
-            .await
-            .unwrap()
-            .run_event_loop(event_rx)
-            .await
-            .unwrap();
+    ui.global::<Bridge>().on_cast_image({
+        let android_app = android_app.clone();
+        move || {
+            call_java_method_no_args(&android_app, JavaMethod::PickImage);
+        }
     });
 
-    ui.run().unwrap();
+    ui
+}
 
-    runtime.spawn(async move {
-        event_tx.send(Event::Quit).unwrap();
-        app_jh.await.unwrap();
+/// Waits until `deadline`, or forever if there is none.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A small black frame and its caps.
+fn warm_up_frame() -> Result<(gst::Caps, gst::Buffer)> {
+    let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, 640, 360).build()?;
+    let mut buffer = gst::Buffer::with_size(info.size())?;
+    {
+        let mut frame =
+            gst_video::VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info)?;
+        // Limited range black
+        frame.plane_data_mut(0)?.fill(16);
+        frame.plane_data_mut(1)?.fill(128);
+        frame.plane_data_mut(2)?.fill(128);
+    }
+    Ok((info.to_caps()?, buffer))
+}
+
+fn set_caps_if_changed(
+    appsrc: &gst_app::AppSrc,
+    caps: &mut Option<gst::Caps>,
+    new_caps: &gst::Caps,
+) {
+    if caps.as_ref() != Some(new_caps) {
+        appsrc.set_caps(Some(new_caps));
+        *caps = Some(new_caps.clone());
+    }
+}
+
+/// Convert a capture timestamp to running time. Frames are stamped with `CLOCK_MONOTONIC`, which is
+/// also what the pipeline's system clock uses. Frames without a usable timestamp get the current
+/// running time like `do-timestamp` would.
+fn capture_running_time(
+    appsrc: &gst_app::AppSrc,
+    capture_ts: Option<gst::ClockTime>,
+) -> Option<gst::ClockTime> {
+    use gst::prelude::*;
+
+    let base_time = appsrc.base_time()?;
+    let now = appsrc.clock()?.time()?;
+    match capture_ts {
+        Some(ts) if ts >= base_time && ts <= now => Some(ts - base_time),
+        _ => now.checked_sub(base_time),
+    }
+}
+
+fn on_preview_sample(
+    appsink: &gst_app::AppSink,
+    ui_weak: &slint::Weak<MainWindow>,
+) -> std::result::Result<gst::FlowSuccess, gst::FlowError> {
+    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+    let buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+    let caps = sample.caps().ok_or(gst::FlowError::Error)?;
+    let video_info = gst_video::VideoInfo::from_caps(caps).map_err(|_| gst::FlowError::Error)?;
+    let frame = gst_video::VideoFrame::from_buffer_readable(buffer, &video_info)
+        .map_err(|_| gst::FlowError::Error)?;
+    if frame.format() != gst_video::VideoFormat::Rgb {
+        error!(format = ?frame.format(), "Received preview buffer with invalid format");
+        return Err(gst::FlowError::NotSupported);
+    }
+
+    let mut pixels =
+        slint::SharedPixelBuffer::<slint::Rgb8Pixel>::new(frame.width(), frame.height());
+    // Rows in the GStreamer frame are padded to 4 bytes, the slint buffer is tightly packed
+    let row_len = frame.width() as usize * 3;
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).map_err(|_| gst::FlowError::Error)?;
+    for (dst, src) in pixels
+        .make_mut_bytes()
+        .chunks_exact_mut(row_len)
+        .zip(data.chunks(stride))
+    {
+        dst.copy_from_slice(&src[..row_len]);
+    }
+
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.global::<Bridge>()
+            .set_cast_preview(slint::Image::from_rgb8(pixels));
     });
 
-    debug!("Finished");
+    Ok(gst::FlowSuccess::Ok)
+}
+
+/// Media type of what `url` points to, judging by the extension of the last path segment. `None`
+/// if there is no extension or it is unknown.
+fn content_type_from_url(url: &str) -> Option<&'static str> {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    // The host is not part of the path, `https://example.com` has no extension
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (_, path) = without_scheme.split_once('/')?;
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if let Some(content_type) =
+        mcore::image_server::content_type_from_path(std::path::Path::new(file_name))
+    {
+        return Some(content_type);
+    }
+
+    let (_, ext) = file_name.rsplit_once('.')?;
+    Some(match ext.to_ascii_lowercase().as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mp3" => "audio/mpeg",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "mpd" => "application/dash+xml",
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain",
+        _ => return None,
+    })
 }
 
 fn jstring_to_string<'local>(env: &mut jni::JNIEnv<'local>, s: &JString<'local>) -> Result<String> {
@@ -553,13 +2428,17 @@ fn jstring_to_string<'local>(env: &mut jni::JNIEnv<'local>, s: &JString<'local>)
 pub extern "C" fn Java_org_fcast_android_sender_FCastDiscoveryListener_serviceFound<'local>(
     mut env: jni::JNIEnv<'local>,
     _class: jni::objects::JClass<'local>,
+    instance: JString<'local>,
     name: JString<'local>,
     addrs: jni::objects::JObject,
     port: jni::sys::jint,
 ) {
-    let name = match jstring_to_string(&mut env, &name) {
-        Ok(name) => name,
-        Err(err) => {
+    let (instance, name) = match (
+        jstring_to_string(&mut env, &instance),
+        jstring_to_string(&mut env, &name),
+    ) {
+        (Ok(instance), Ok(name)) => (instance, name),
+        (Err(err), _) | (_, Err(err)) => {
             error!(?err, "Failed to convert jstring to string");
             return;
         }
@@ -643,12 +2522,17 @@ pub extern "C" fn Java_org_fcast_android_sender_FCastDiscoveryListener_serviceFo
         });
     }
 
+    mcore::transmission::sort_addresses_by_preference(&mut ip_addrs);
+
     let device_info = fcast_sender_sdk::device::DeviceInfo::fcast(name, ip_addrs, port);
-    debug!(?device_info, "Found device");
+    debug!(instance, ?device_info, "Found device");
 
     log_err!(
-        GLOB_EVENT_CHAN.0.send(Event::DeviceAvailable(device_info)),
-        "Failed to send device available event"
+        send_global_event(Event::ServiceFound {
+            instance,
+            device_info
+        }),
+        "Failed to send service found event"
     );
 }
 
@@ -657,12 +2541,12 @@ pub extern "C" fn Java_org_fcast_android_sender_FCastDiscoveryListener_serviceFo
 pub extern "C" fn Java_org_fcast_android_sender_FCastDiscoveryListener_serviceLost<'local>(
     mut env: jni::JNIEnv<'local>,
     _class: jni::objects::JClass<'local>,
-    name: jni::objects::JString<'local>,
+    instance: jni::objects::JString<'local>,
 ) {
-    match jstring_to_string(&mut env, &name) {
-        Ok(name) => log_err!(
-            GLOB_EVENT_CHAN.0.send(Event::DeviceRemoved(name)),
-            "Failed to send device removed event"
+    match jstring_to_string(&mut env, &instance) {
+        Ok(instance) => log_err!(
+            send_global_event(Event::ServiceLost(instance)),
+            "Failed to send service lost event"
         ),
         Err(err) => error!(?err, "Failed to convert jstring to string"),
     }
@@ -673,10 +2557,12 @@ pub extern "C" fn Java_org_fcast_android_sender_FCastDiscoveryListener_serviceLo
 pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeCaptureStarted<'local>(
     _env: jni::JNIEnv<'local>,
     _class: jni::objects::JClass<'local>,
+    with_audio: jni::sys::jboolean,
 ) {
-    debug!("Screen capture was started");
+    let with_audio = with_audio == jni::sys::JNI_TRUE;
+    debug!(with_audio, "Screen capture was started");
     log_err!(
-        GLOB_EVENT_CHAN.0.send(Event::CaptureStarted),
+        send_global_event(Event::CaptureStarted { with_audio }),
         "Failed to send capture started event"
     );
 }
@@ -689,7 +2575,7 @@ pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeCaptureStoppe
 ) {
     debug!("Screen capture was stopped");
     log_err!(
-        GLOB_EVENT_CHAN.0.send(Event::CaptureStopped),
+        send_global_event(Event::CaptureStopped),
         "Failed to send capture stopped event"
     );
 }
@@ -702,150 +2588,295 @@ pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeCaptureCancel
 ) {
     debug!("Screen capture was cancelled");
     log_err!(
-        GLOB_EVENT_CHAN.0.send(Event::CaptureCancelled),
+        send_global_event(Event::CaptureCancelled),
         "Failed to send capture cancelled event"
     );
 }
 
-fn process_frame<'local>(
-    env: jni::JNIEnv<'local>,
-    width: jni::sys::jint,
-    height: jni::sys::jint,
-    buffer_y: JByteBuffer<'local>,
-    buffer_u: JByteBuffer<'local>,
-    buffer_v: JByteBuffer<'local>,
-) -> Result<()> {
-    let width = width as usize;
-    let height = height as usize;
+/// Number of samples per row and number of rows of each plane. Interleaved chroma planes count
+/// each byte as a sample.
+fn plane_dims(
+    format: gst_video::VideoFormat,
+    width: usize,
+    height: usize,
+) -> Result<Vec<(usize, usize)>> {
+    let chroma_width = width / 2;
+    let chroma_height = height / 2;
+    Ok(match format {
+        gst_video::VideoFormat::I420 => vec![
+            (width, height),
+            (chroma_width, chroma_height),
+            (chroma_width, chroma_height),
+        ],
+        // U and V are interleaved in a single plane
+        gst_video::VideoFormat::Nv12 | gst_video::VideoFormat::Nv21 => {
+            vec![(width, height), (chroma_width * 2, chroma_height)]
+        }
+        // Like NV12 with 16-bit samples
+        gst_video::VideoFormat::P01010le => {
+            vec![(width * 2, height), (chroma_width * 4, chroma_height)]
+        }
+        _ => bail!("Unsupported capture format: {format:?}"),
+    })
+}
 
-    fn buffer_as_slice<'local>(
-        env: &jni::JNIEnv<'local>,
-        buffer: &JByteBuffer<'local>,
-        size: usize,
-    ) -> Result<&'local [u8]> {
-        let buffer_cap = match env.get_direct_buffer_capacity(&buffer) {
-            Ok(cap) => cap,
-            Err(err) => {
-                bail!("Failed to get capacity of the byte buffer: {err}");
-            }
-        };
+/// Full range BT.709, what the GLES capture path produces.
+fn sdr_colorimetry() -> VideoColorimetry {
+    VideoColorimetry::new(
+        gst_video::VideoColorRange::Range0_255,
+        gst_video::VideoColorMatrix::Bt709,
+        gst_video::VideoTransferFunction::Bt709,
+        gst_video::VideoColorPrimaries::Bt709,
+    )
+}
 
-        if buffer_cap < size {
-            bail!("buffer_cap < size: {buffer_cap} < {size}");
-        }
+/// Limited range BT.2020 as produced by 10-bit `ImageReader` capture, with the PQ transfer
+/// function if the content is HDR.
+fn bt2020_colorimetry(hdr: bool) -> VideoColorimetry {
+    VideoColorimetry::new(
+        gst_video::VideoColorRange::Range16_235,
+        gst_video::VideoColorMatrix::Bt2020,
+        if hdr {
+            gst_video::VideoTransferFunction::Smpte2084
+        } else {
+            gst_video::VideoTransferFunction::Bt202010
+        },
+        gst_video::VideoColorPrimaries::Bt2020,
+    )
+}
 
-        let buffer_ptr = match env.get_direct_buffer_address(&buffer) {
-            Ok(ptr) => {
-                assert!(!ptr.is_null());
-                ptr
-            }
-            Err(err) => {
-                bail!("Failed to get buffer address: {err}");
-            }
-        };
+/// A plane as described by `android.media.Image.Plane`.
+struct SrcPlane<'local> {
+    data: &'local [u8],
+    /// Distance in bytes between the start of two rows, at least the row length and often
+    /// larger because of alignment
+    row_stride: usize,
+    /// Distance in bytes between two samples of the plane, 2 when the chroma planes of a
+    /// `YUV_420_888` image are backed by interleaved memory
+    pixel_stride: usize,
+}
 
-        unsafe { Ok(std::slice::from_raw_parts(buffer_ptr, buffer_cap)) }
+impl<'local> SrcPlane<'local> {
+    fn new(
+        env: &jni::JNIEnv<'local>,
+        buffer: &JByteBuffer<'local>,
+        row_stride: jni::sys::jint,
+        pixel_stride: jni::sys::jint,
+    ) -> Result<Self> {
+        Self::from_slice(buffer_as_slice(env, buffer, 0)?, row_stride, pixel_stride)
     }
 
-    let slice_y = buffer_as_slice(&env, &buffer_y, width * height)?;
-    let slice_u = buffer_as_slice(&env, &buffer_u, (width / 2) * (height / 2))?;
-    let slice_v = buffer_as_slice(&env, &buffer_v, (width / 2) * (height / 2))?;
+    fn from_slice(
+        data: &'local [u8],
+        row_stride: jni::sys::jint,
+        pixel_stride: jni::sys::jint,
+    ) -> Result<Self> {
+        if row_stride <= 0 || pixel_stride <= 0 {
+            bail!("Invalid plane strides: row_stride={row_stride} pixel_stride={pixel_stride}");
+        }
+        Ok(Self {
+            data,
+            row_stride: row_stride as usize,
+            pixel_stride: pixel_stride as usize,
+        })
+    }
+}
 
-    let info = match gst_video::VideoInfo::builder(
-        gst_video::VideoFormat::I420,
-        width as u32,
-        height as u32,
-    )
-    .colorimetry(&VideoColorimetry::new(
-        gst_video::VideoColorRange::Range0_255,
-        gst_video::VideoColorMatrix::Bt709,
-        gst_video::VideoTransferFunction::Bt709,
-        gst_video::VideoColorPrimaries::Bt709,
+/// The U and V planes of a `YUV_420_888` image with a chroma pixel stride of 2 are views of the
+/// same interleaved memory, offset by one sample. Returns that memory as a single plane and
+/// whether V comes first, i.e. the image is NV21 rather than NV12.
+fn interleaved_chroma<'local>(
+    env: &jni::JNIEnv<'local>,
+    buffer_u: &JByteBuffer<'local>,
+    buffer_v: &JByteBuffer<'local>,
+    sample_size: usize,
+) -> Result<(&'local [u8], bool)> {
+    let u = buffer_as_slice(env, buffer_u, 0)?;
+    let v = buffer_as_slice(env, buffer_v, 0)?;
+    let (first, second, v_first) = if v.as_ptr() == u.as_ptr().wrapping_add(sample_size) {
+        (u, v, false)
+    } else if u.as_ptr() == v.as_ptr().wrapping_add(sample_size) {
+        (v, u, true)
+    } else {
+        bail!("U and V planes are not interleaved");
+    };
+    // Each view stops at its own last sample, so the second one reaches to the end of the plane
+    let len = (second.len() + sample_size).max(first.len());
+    // SAFETY: both views point into the same image buffer, which spans from the start of the
+    // first view to the end of the second
+    Ok((
+        unsafe { std::slice::from_raw_parts(first.as_ptr(), len) },
+        v_first,
     ))
-    .build()
-    {
-        Ok(info) => info,
+}
+
+fn buffer_as_slice<'local>(
+    env: &jni::JNIEnv<'local>,
+    buffer: &JByteBuffer<'local>,
+    size: usize,
+) -> Result<&'local [u8]> {
+    let buffer_cap = match env.get_direct_buffer_capacity(&buffer) {
+        Ok(cap) => cap,
         Err(err) => {
-            bail!("Failed to crate video info: {err}");
+            bail!("Failed to get capacity of the byte buffer: {err}");
         }
     };
 
-    let new_caps = match info.to_caps() {
-        Ok(caps) => caps,
+    if buffer_cap < size {
+        bail!("buffer_cap < size: {buffer_cap} < {size}");
+    }
+
+    let buffer_ptr = match env.get_direct_buffer_address(&buffer) {
+        Ok(ptr) => {
+            assert!(!ptr.is_null());
+            ptr
+        }
         Err(err) => {
-            bail!("Failed to create caps from video info: {err}");
+            bail!("Failed to get buffer address: {err}");
         }
     };
 
-    fn init_frame_pool(
-        pool: &gst_video::VideoBufferPool,
-        mut old_config: gst::BufferPoolConfig,
-        new_caps: &gst::Caps,
-        frame_size: u32,
-    ) -> Result<()> {
-        pool.set_config({
-            old_config.set_params(Some(&new_caps), frame_size, 1, 30);
-            old_config
-        })?;
-        pool.set_active(true)?;
-        Ok(())
+    unsafe { Ok(std::slice::from_raw_parts(buffer_ptr, buffer_cap)) }
+}
+
+/// Frames are skipped rather than copied when the display refreshes faster than `max_framerate`.
+/// A quarter of the interval is allowed as slack so capture jitter doesn't skip frames that are
+/// just about on time, e.g. every other frame of a 60 Hz display is kept for 30 fps.
+fn min_frame_interval(max_framerate: u32) -> std::time::Duration {
+    match max_framerate {
+        0 => std::time::Duration::ZERO,
+        fps => std::time::Duration::from_secs(1) * 3 / (fps * 4),
     }
+}
 
-    let mut frame_pool = FRAME_POOL.lock();
-    let old_config = frame_pool.config();
-    let frame_size = width * height + 2 * ((width / 2) * (height / 2));
-    if !frame_pool.is_active() {
-        init_frame_pool(&frame_pool, old_config, &new_caps, frame_size as u32)?;
-    } else {
-        let _ = frame_pool.set_active(false);
-        let new_frame_pool = gst_video::VideoBufferPool::new();
-        init_frame_pool(&new_frame_pool, old_config, &new_caps, frame_size as u32)?;
-        *frame_pool = new_frame_pool;
+fn process_frame(
+    format: gst_video::VideoFormat,
+    colorimetry: &VideoColorimetry,
+    width: jni::sys::jint,
+    height: jni::sys::jint,
+    planes: &[SrcPlane<'_>],
+    timestamp_ns: jni::sys::jlong,
+) -> Result<()> {
+    let width = width as usize;
+    let height = height as usize;
+
+    let config = *CAPTURE_CONFIG.lock();
+    {
+        let now = std::time::Instant::now();
+        let mut last_frame = LAST_FRAME_INSTANT.lock();
+        if last_frame.is_some_and(|last| now.duration_since(last) < config.min_frame_interval) {
+            CAPTURE_STATS.skipped_frames.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        *last_frame = Some(now);
     }
 
-    let buffer = match frame_pool.acquire_buffer(None) {
+    let process_start = std::time::Instant::now();
+    let dims = plane_dims(format, width, height)?;
+    if dims.len() != planes.len() {
+        bail!(
+            "{format:?} needs {} planes but got {}",
+            dims.len(),
+            planes.len()
+        );
+    }
+
+    let info = match gst_video::VideoInfo::builder(format, width as u32, height as u32)
+        .colorimetry(colorimetry)
+        .build()
+    {
+        Ok(info) => info,
+        Err(err) => {
+            bail!("Failed to crate video info: {err}");
+        }
+    };
+
+    let mut buffer = match FRAME_POOLS.lock().get(&info)?.acquire_buffer(None) {
         Ok(buffer) => buffer,
         Err(err) => {
             bail!("Failed to acquire buffer from pool: {err}");
         }
     };
+    // Absolute capture time, converted to running time when the frame is pushed into the pipeline
+    buffer
+        .make_mut()
+        .set_pts((timestamp_ns > 0).then(|| gst::ClockTime::from_nseconds(timestamp_ns as u64)));
     let Ok(mut vframe) = gst_video::VideoFrame::from_buffer_writable(buffer, &info) else {
         bail!("Failed to crate VideoFrame from buffer");
     };
 
-    fn copy(
-        vframe: &mut gst_video::VideoFrame<gst_video::video_frame::Writable>,
-        plane_idx: u32,
-        src_plane: &[u8],
-    ) -> Result<()> {
-        let dest_y_stride = *vframe
+    for (plane_idx, (plane, (row_len, rows))) in planes.iter().zip(dims).enumerate() {
+        if rows == 0 || row_len == 0 {
+            continue;
+        }
+        let min_row_stride = (row_len - 1) * plane.pixel_stride + 1;
+        if plane.row_stride < min_row_stride {
+            bail!(
+                "Row stride of plane {plane_idx} is too small: {} < {min_row_stride}",
+                plane.row_stride
+            );
+        }
+        let size = (rows - 1) * plane.row_stride + min_row_stride;
+        if plane.data.len() < size {
+            bail!(
+                "Plane {plane_idx} is too small: {} < {size}",
+                plane.data.len()
+            );
+        }
+        let dest_stride = *vframe
             .plane_stride()
-            .get(plane_idx as usize)
+            .get(plane_idx)
             .ok_or(anyhow::anyhow!("Could not get plane stride"))?
             as usize;
-        let dest_y = vframe.plane_data_mut(plane_idx)?;
-        for (dest, src) in dest_y
-            .chunks_exact_mut(dest_y_stride)
-            .zip(src_plane.chunks_exact(dest_y_stride))
-        {
-            dest[..dest_y_stride].copy_from_slice(&src[..dest_y_stride]);
+        mcore::plane_copy::copy_plane(
+            vframe.plane_data_mut(plane_idx as u32)?,
+            dest_stride,
+            plane.data,
+            (row_len, rows),
+            plane.row_stride,
+            plane.pixel_stride,
+        );
+    }
+    CAPTURE_STATS.record_frame_process_time(process_start.elapsed());
+
+    let (tx, rx) = &*FRAME_QUEUE;
+    if tx.len() >= config.max_queued_frames {
+        // The pipeline fell behind
+        CAPTURE_STATS.dropped_frames.fetch_add(1, Ordering::Relaxed);
+        match config.drop_policy {
+            FrameDropPolicy::PreferDrop => {
+                let _ = rx.try_recv();
+            }
+            FrameDropPolicy::PreferLatency => return Ok(()),
         }
-
-        Ok(())
     }
-
-    copy(&mut vframe, 0, slice_y)?;
-    copy(&mut vframe, 1, slice_u)?;
-    copy(&mut vframe, 2, slice_v)?;
-
-    let (lock, cvar) = &*FRAME_PAIR;
-    let mut frame = lock.lock();
-    *frame = Some(vframe);
-    cvar.notify_one();
+    // `max_queued_frames` is clamped to the queue's capacity so this only fails if the queue
+    // is disconnected
+    if let Err(err) = tx.try_send(vframe) {
+        bail!("Failed to queue frame: {err}");
+    }
 
     Ok(())
 }
 
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeFrameSizeChanged<'local>(
+    _env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    width: jni::sys::jint,
+    height: jni::sys::jint,
+) {
+    if width <= 0 || height <= 0 {
+        error!(width, height, "Invalid frame size");
+        return;
+    }
+    log_err!(
+        FRAME_POOLS.lock().prepare(width as u32, height as u32),
+        "Failed to prepare frame pool"
+    );
+}
+
 #[allow(non_snake_case)]
 #[unsafe(no_mangle)]
 pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeProcessFrame<'local>(
@@ -854,14 +2885,177 @@ pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeProcessFrame<
     width: jni::sys::jint,
     height: jni::sys::jint,
     buffer_y: JByteBuffer<'local>,
+    row_stride_y: jni::sys::jint,
+    buffer_u: JByteBuffer<'local>,
+    row_stride_u: jni::sys::jint,
+    pixel_stride_u: jni::sys::jint,
+    buffer_v: JByteBuffer<'local>,
+    row_stride_v: jni::sys::jint,
+    pixel_stride_v: jni::sys::jint,
+    timestamp_ns: jni::sys::jlong,
+) {
+    let res = (|| {
+        let planes = [
+            SrcPlane::new(&env, &buffer_y, row_stride_y, 1)?,
+            SrcPlane::new(&env, &buffer_u, row_stride_u, pixel_stride_u)?,
+            SrcPlane::new(&env, &buffer_v, row_stride_v, pixel_stride_v)?,
+        ];
+        process_frame(
+            gst_video::VideoFormat::I420,
+            &sdr_colorimetry(),
+            width,
+            height,
+            &planes,
+            timestamp_ns,
+        )
+    })();
+    if let Err(err) = res {
+        error!(?err, "Failed to process frame");
+    }
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeProcessSemiPlanarFrame<
+    'local,
+>(
+    env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    width: jni::sys::jint,
+    height: jni::sys::jint,
+    buffer_y: JByteBuffer<'local>,
+    row_stride_y: jni::sys::jint,
+    buffer_u: JByteBuffer<'local>,
+    buffer_v: JByteBuffer<'local>,
+    row_stride_uv: jni::sys::jint,
+    timestamp_ns: jni::sys::jlong,
+) {
+    let res = (|| {
+        let (chroma, nv21) = interleaved_chroma(&env, &buffer_u, &buffer_v, 1)?;
+        let format = if nv21 {
+            gst_video::VideoFormat::Nv21
+        } else {
+            gst_video::VideoFormat::Nv12
+        };
+        let planes = [
+            SrcPlane::new(&env, &buffer_y, row_stride_y, 1)?,
+            SrcPlane::from_slice(chroma, row_stride_uv, 1)?,
+        ];
+        process_frame(
+            format,
+            &sdr_colorimetry(),
+            width,
+            height,
+            &planes,
+            timestamp_ns,
+        )
+    })();
+    if let Err(err) = res {
+        error!(?err, "Failed to process frame");
+    }
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeProcessP010Frame<'local>(
+    env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    width: jni::sys::jint,
+    height: jni::sys::jint,
+    hdr: jni::sys::jboolean,
+    buffer_y: JByteBuffer<'local>,
+    row_stride_y: jni::sys::jint,
     buffer_u: JByteBuffer<'local>,
     buffer_v: JByteBuffer<'local>,
+    row_stride_uv: jni::sys::jint,
+    timestamp_ns: jni::sys::jlong,
 ) {
-    if let Err(err) = process_frame(env, width, height, buffer_y, buffer_u, buffer_v) {
+    let res = (|| {
+        let (chroma, v_first) = interleaved_chroma(&env, &buffer_u, &buffer_v, 2)?;
+        if v_first {
+            bail!("P010 chroma samples are in Cr, Cb order");
+        }
+        let planes = [
+            SrcPlane::new(&env, &buffer_y, row_stride_y, 1)?,
+            SrcPlane::from_slice(chroma, row_stride_uv, 1)?,
+        ];
+        process_frame(
+            gst_video::VideoFormat::P01010le,
+            &bt2020_colorimetry(hdr == jni::sys::JNI_TRUE),
+            width,
+            height,
+            &planes,
+            timestamp_ns,
+        )
+    })();
+    if let Err(err) = res {
         error!(?err, "Failed to process frame");
     }
 }
 
+/// Push a block of interleaved 16-bit PCM into the audio source of the active capture session.
+fn process_audio_frame<'local>(
+    env: &jni::JNIEnv<'local>,
+    buffer: &JByteBuffer<'local>,
+    size: jni::sys::jint,
+    sample_rate: jni::sys::jint,
+    channels: jni::sys::jint,
+    timestamp_ns: jni::sys::jlong,
+) -> Result<()> {
+    // Audio that arrives before the pipeline is set up or after it was torn down is dropped
+    let Some(appsrc) = AUDIO_SRC.lock().clone() else {
+        return Ok(());
+    };
+    if size <= 0 || sample_rate <= 0 || !(1..=2).contains(&channels) {
+        bail!("Invalid audio frame: size={size} sample_rate={sample_rate} channels={channels}");
+    }
+
+    let caps = gst::Caps::builder("audio/x-raw")
+        .field("format", "S16LE")
+        .field("layout", "interleaved")
+        .field("rate", sample_rate)
+        .field("channels", channels)
+        .build();
+    if appsrc.caps().as_ref() != Some(&caps) {
+        appsrc.set_caps(Some(&caps));
+    }
+
+    let size = size as usize;
+    let data = buffer_as_slice(env, buffer, size)?;
+    let samples = (size / (2 * channels as usize)) as u64;
+    let mut audio_buffer = gst::Buffer::from_slice(data[..size].to_vec());
+    {
+        let audio_buffer = audio_buffer.make_mut();
+        let capture_ts =
+            (timestamp_ns > 0).then(|| gst::ClockTime::from_nseconds(timestamp_ns as u64));
+        audio_buffer.set_pts(capture_running_time(&appsrc, capture_ts));
+        audio_buffer.set_duration(gst::ClockTime::from_nseconds(
+            samples * gst::ClockTime::SECOND.nseconds() / sample_rate as u64,
+        ));
+    }
+
+    appsrc.push_buffer(audio_buffer)?;
+
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeProcessAudioFrame<'local>(
+    env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    buffer: JByteBuffer<'local>,
+    size: jni::sys::jint,
+    sample_rate: jni::sys::jint,
+    channels: jni::sys::jint,
+    timestamp_ns: jni::sys::jlong,
+) {
+    if let Err(err) = process_audio_frame(&env, &buffer, size, sample_rate, channels, timestamp_ns)
+    {
+        error!(?err, "Failed to process audio frame");
+    }
+}
+
 #[allow(non_snake_case)]
 #[unsafe(no_mangle)]
 pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeQrScanResult<'local>(
@@ -871,9 +3065,288 @@ pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeQrScanResult<
 ) {
     match jstring_to_string(&mut env, &result) {
         Ok(result) => log_err!(
-            GLOB_EVENT_CHAN.0.send(Event::QrScanResult(result)),
+            send_global_event(Event::QrScanResult(result)),
             "Failed to send device removed event"
         ),
         Err(err) => error!(?err, "Failed to convert jstring to string"),
     }
 }
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeCastImage<'local>(
+    mut env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    path: jni::objects::JString<'local>,
+    mime_type: jni::objects::JString<'local>,
+) {
+    let path = match jstring_to_string(&mut env, &path) {
+        Ok(path) => path,
+        Err(err) => {
+            error!(?err, "Failed to convert jstring to string");
+            return;
+        }
+    };
+    // The picker does not know the type of every file
+    let content_type = if mime_type.is_null() {
+        None
+    } else {
+        match jstring_to_string(&mut env, &mime_type) {
+            Ok(mime_type) => Some(mime_type),
+            Err(err) => {
+                error!(?err, "Failed to convert jstring to string");
+                None
+            }
+        }
+    };
+    log_err!(
+        send_global_event(Event::CastImage {
+            path: path.into(),
+            content_type,
+        }),
+        "Failed to send cast image event"
+    );
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeNetworkChanged<'local>(
+    _env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    network_type: jni::sys::jint,
+    has_internet: jni::sys::jboolean,
+) {
+    // Must match the NETWORK_TYPE_* constants in MainActivity
+    let network_type = match network_type {
+        0 => mcore::NetworkType::None,
+        1 => mcore::NetworkType::Wifi,
+        2 => mcore::NetworkType::Cellular,
+        3 => mcore::NetworkType::Ethernet,
+        _ => mcore::NetworkType::Other,
+    };
+    log_err!(
+        send_global_event(Event::NetworkChanged {
+            network_type,
+            has_internet: has_internet == jni::sys::JNI_TRUE,
+        }),
+        "Failed to send network changed event"
+    );
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeSetHeadless<'local>(
+    _env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    headless: jni::sys::jboolean,
+) {
+    HEADLESS.store(headless == jni::sys::JNI_TRUE, Ordering::Relaxed);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeConnectReceiver<'local>(
+    mut env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    device_id: jni::objects::JString<'local>,
+) {
+    match jstring_to_string(&mut env, &device_id) {
+        Ok(device_id) => log_err!(
+            send_global_event(Event::ConnectToDevice(device_id)),
+            "Failed to send connect to device event"
+        ),
+        Err(err) => error!(?err, "Failed to convert jstring to string"),
+    }
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeStartCast<'local>(
+    mut env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    options: jni::objects::JString<'local>,
+) {
+    let options = match jstring_to_string(&mut env, &options) {
+        Ok(options) => options,
+        Err(err) => {
+            error!(?err, "Failed to convert jstring to string");
+            return;
+        }
+    };
+    let options = match serde_json::from_str::<StartCastOptions>(&options) {
+        Ok(options) => options,
+        Err(err) => {
+            error!(?err, options, "Invalid start cast options");
+            return;
+        }
+    };
+    log_err!(
+        send_global_event(Event::StartCast {
+            scale_width: options.scale_width,
+            scale_height: options.scale_height,
+            max_framerate: options.max_framerate,
+            record: options.record,
+            debug_overlay: options.debug_overlay,
+            latency_preset: options.latency_preset,
+            ice: options.ice,
+            max_duration: (options.max_duration_secs > 0)
+                .then(|| std::time::Duration::from_secs(options.max_duration_secs)),
+        }),
+        "Failed to send start cast event"
+    );
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeStopCast<'local>(
+    _env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+) {
+    log_err!(
+        send_global_event(Event::EndSession { disconnect: true }),
+        "Failed to send end session event"
+    );
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_org_fcast_android_sender_MainActivity_nativeSetDebug<'local>(
+    mut env: jni::JNIEnv<'local>,
+    _class: jni::objects::JClass<'local>,
+    threshold: jni::objects::JString<'local>,
+    stream_logs: jni::sys::jboolean,
+) {
+    match jstring_to_string(&mut env, &threshold) {
+        Ok(threshold) => log_err!(
+            send_global_event(Event::SetGstDebug {
+                threshold,
+                stream_logs: stream_logs == jni::sys::JNI_TRUE,
+            }),
+            "Failed to send set GStreamer debug event"
+        ),
+        Err(err) => error!(?err, "Failed to convert jstring to string"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_addresses_split_by_family() {
+        let v4 = fcast_sender_sdk::IpAddr::v4(192, 168, 1, 2);
+        let v4_new = fcast_sender_sdk::IpAddr::v4(192, 168, 1, 3);
+        let v6 = fcast_sender_sdk::IpAddr::from(std::net::IpAddr::V6(Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 1,
+        )));
+
+        let mut addresses = vec![v4];
+        // The IPv6 resolve must not drop the IPv4 address found by the other resolve
+        merge_addresses(&mut addresses, vec![v6]);
+        assert_eq!(addresses, vec![v4, v6]);
+        // A later IPv4 resolve replaces the stale IPv4 address but keeps the IPv6 one
+        merge_addresses(&mut addresses, vec![v4_new]);
+        assert_eq!(addresses, vec![v6, v4_new]);
+    }
+
+    #[test]
+    fn test_start_cast_options() {
+        let options: StartCastOptions = serde_json::from_str(
+            r#"{"maxFramerate":60,"record":true,"latencyPreset":"UltraLow","ice":{"noHostOnCellular":false,"noMdns":true,"turnServer":null}}"#,
+        )
+        .unwrap();
+        assert_eq!(options.max_framerate, 60);
+        assert_eq!(options.scale_width, 1920);
+        assert!(options.record);
+        assert_eq!(options.latency_preset, LatencyPreset::UltraLow);
+        assert!(options.ice.no_mdns);
+        assert_eq!(options.max_duration_secs, 0);
+    }
+
+    #[test]
+    fn test_min_frame_interval() {
+        let display_interval = std::time::Duration::from_secs(1) / 60;
+        assert!(min_frame_interval(30) > display_interval);
+        assert!(min_frame_interval(30) < display_interval * 2);
+        assert!(min_frame_interval(60) < display_interval);
+        assert_eq!(min_frame_interval(0), std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn test_is_valid_transition() {
+        use AppState::*;
+
+        let allowed = [
+            (Disconnected, Disconnected),
+            (Disconnected, Connecting),
+            (Connecting, Connecting),
+            (Connecting, SelectingSettings),
+            (Connecting, Disconnected),
+            (SelectingSettings, WaitingForMedia),
+            (SelectingSettings, Disconnected),
+            (WaitingForMedia, Casting),
+            (WaitingForMedia, SelectingSettings),
+            (WaitingForMedia, Disconnected),
+            (Casting, SelectingSettings),
+            (Casting, Disconnected),
+        ];
+        for (from, to) in allowed {
+            assert!(
+                AppStateMachine::is_valid_transition(from, to),
+                "{from:?} -> {to:?} should be allowed"
+            );
+        }
+
+        let forbidden = [
+            (Disconnected, SelectingSettings),
+            (Disconnected, WaitingForMedia),
+            (Disconnected, Casting),
+            (Connecting, WaitingForMedia),
+            (Connecting, Casting),
+            (SelectingSettings, Connecting),
+            (SelectingSettings, SelectingSettings),
+            (SelectingSettings, Casting),
+            (WaitingForMedia, Connecting),
+            (Casting, Connecting),
+            (Casting, WaitingForMedia),
+            (Casting, Casting),
+        ];
+        for (from, to) in forbidden {
+            assert!(
+                !AppStateMachine::is_valid_transition(from, to),
+                "{from:?} -> {to:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_content_type_from_url() {
+        let cases = [
+            ("https://example.com/video.mp4", Some("video/mp4")),
+            (
+                "https://example.com/live/playlist.M3U8?token=1",
+                Some("application/vnd.apple.mpegurl"),
+            ),
+            ("http://example.com/photo.jpg#top", Some("image/jpeg")),
+            ("https://example.com/notes.txt", Some("text/plain")),
+            ("https://example.com", None),
+            ("https://example.com/", None),
+            ("https://example.com/watch?v=abc.mp4", None),
+            ("https://example.com/archive.zip", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(content_type_from_url(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_rejected_transition_keeps_state() {
+        let mut state = AppStateMachine::new();
+        assert!(!state.transition(AppState::Casting));
+        assert_eq!(state.state, AppState::Disconnected);
+
+        assert!(state.transition(AppState::Connecting));
+        assert!(state.transition(AppState::SelectingSettings));
+        assert_eq!(state.state, AppState::SelectingSettings);
+    }
+}
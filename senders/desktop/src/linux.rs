@@ -66,7 +66,7 @@ fn get_x11_targets(conn: &xcb::Connection) -> Result<Vec<(usize, VideoSource)>>
 
 pub async fn video_source_fetch_worker(
     mut rx: tokio::sync::mpsc::Receiver<FetchEvent>,
-    event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    event_tx: mcore::event_bus::EventSender,
 ) {
     let mut _proxy = None;
     let mut _session: Option<ashpd::desktop::Session<'_, ashpd::desktop::screencast::Screencast>> =
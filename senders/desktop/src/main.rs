@@ -19,6 +19,7 @@ use image::ImageFormat;
 use mcore::VideoSource;
 use mcore::{
     AudioSource, Event, FileSystemEntry, MediaFileEntry, RootDirType, ShouldQuit,
+    event_bus::{EventReceiver, EventSender},
     transmission::WhepSink,
 };
 use serde::{Deserialize, Serialize};
@@ -88,7 +89,7 @@ async fn list_directory(
     canceler: Canceler,
     id: u32,
     path: PathBuf,
-    event_tx: UnboundedSender<Event>,
+    event_tx: EventSender,
 ) -> Result<()> {
     let mut dir_entries = tokio::fs::read_dir(&path).await?;
     let mut entries = Vec::new();
@@ -130,7 +131,7 @@ async fn process_files(
     id: u32,
     mut root_path: PathBuf,
     files: Vec<String>,
-    event_tx: UnboundedSender<Event>,
+    event_tx: EventSender,
 ) -> Result<()> {
     let mut media_files = Vec::new();
     for name in files {
@@ -470,7 +471,7 @@ struct SessionState {
 struct Application {
     cast_ctx: CastContext,
     ui_weak: slint::Weak<MainWindow>,
-    event_tx: UnboundedSender<Event>,
+    event_tx: EventSender,
     devices: HashMap<String, DeviceInfo>,
     current_session_id: usize,
     current_local_media_id: u32,
@@ -482,7 +483,7 @@ struct Application {
     update: Option<mcore::Release>,
 }
 
-async fn spawn_video_source_fetcher(event_tx: UnboundedSender<Event>) -> Sender<FetchEvent> {
+async fn spawn_video_source_fetcher(event_tx: EventSender) -> Sender<FetchEvent> {
     #[allow(unused_mut)]
     let (video_source_fetcher_tx, mut video_source_fetcher_rx) = channel::<FetchEvent>(10);
 
@@ -610,7 +611,7 @@ fn ipv6_is_global(v6: std::net::Ipv6Addr) -> bool {
 
 impl Application {
     /// Must be called from a tokio runtime.
-    pub fn new(ui_weak: slint::Weak<MainWindow>, event_tx: UnboundedSender<Event>) -> Result<Self> {
+    pub fn new(ui_weak: slint::Weak<MainWindow>, event_tx: EventSender) -> Result<Self> {
         let cast_ctx = CastContext::new()?;
         cast_ctx.start_discovery(Arc::new(mcore::Discoverer::new(event_tx.clone())));
 
@@ -1295,6 +1296,7 @@ impl Application {
                 None => error!(device_name, "Device not found"),
             },
             Event::SignallerStarted {
+                sink_id,
                 bound_port_v4,
                 bound_port_v6,
             } => {
@@ -1316,10 +1318,13 @@ impl Application {
                                 fcast_sender_sdk::IpAddr::V6 { .. } => bound_port_v6,
                             };
 
-                            let (content_type, url) = tx_sink
-                                .as_ref()
-                                .unwrap()
-                                .get_play_msg((&addr).into(), bound_port);
+                            let Some(tx_sink) = tx_sink.as_ref().filter(|sink| sink.id() == sink_id)
+                            else {
+                                debug!(sink_id, "Ignoring signaller of a stopped sink");
+                                return Ok(ShouldQuit::No);
+                            };
+                            let (content_type, url) =
+                                tx_sink.get_play_msg((&addr).into(), bound_port);
 
                             debug!(content_type, url, "Sending play message");
 
@@ -2280,7 +2285,7 @@ impl Application {
 
     pub async fn run_event_loop(
         mut self,
-        mut event_rx: tokio::sync::mpsc::UnboundedReceiver<Event>,
+        mut event_rx: EventReceiver,
     ) -> Result<()> {
         tracing_gstreamer::integrate_events();
         gst::log::remove_default_log_function();
@@ -2523,7 +2528,7 @@ fn main() -> Result<()> {
 
     let runtime = Runtime::new()?;
 
-    let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+    let (event_tx, event_rx) = mcore::event_bus::channel();
 
     let ui = MainWindow::new()?;
 